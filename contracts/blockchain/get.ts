@@ -8,10 +8,10 @@ export async function Get(req: Request) {
 
   try {
     // Get blockchain context
-    const height = block.getHeight()
-    const timestamp = block.getTimestamp()
-    const gasLimit = block.getGasLimit()
-    const gasUsed = block.getGasUsed()
+    const height = block.height
+    const timestamp = block.timestamp
+    const gasLimit = block.gasLimit
+    const gasUsed = block.gasUsed
 
     console.log('Block info:', { height, timestamp, gasLimit, gasUsed })
 
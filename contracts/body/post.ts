@@ -0,0 +1,25 @@
+// Reads the raw body by Content-Type: try
+//   curl -d 'a=1&a=2&b=3' localhost:8180/body
+//   curl -F name=tana -F file=@README.md localhost:8180/body
+//   curl -H 'Content-Type: application/json' -d '{"x":1}' localhost:8180/body
+import { Request, Response } from 'tana/net'
+
+export async function Post(req: Request) {
+  const contentType = req.headers['content-type'] || ''
+
+  if (contentType.startsWith('application/json')) {
+    return Response.json({ json: await req.json() })
+  }
+
+  if (contentType.startsWith('application/x-www-form-urlencoded') || contentType.startsWith('multipart/form-data')) {
+    const form = await req.formData()
+    const fields = Object.fromEntries(
+      Object.entries(form).map(([name, value]: [string, any]) =>
+        [name, value?.data ? { filename: value.filename, type: value.type, size: value.data.length } : value])
+    )
+    return Response.json({ form: fields })
+  }
+
+  const bytes = await req.arrayBuffer()
+  return Response.json({ text: await req.text(), size: bytes.byteLength })
+}
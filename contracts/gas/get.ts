@@ -0,0 +1,19 @@
+// Gas is charged per op: block.gasUsed grows with every data write
+import { Request, Response } from 'tana/net'
+import { block } from 'tana/block'
+import { data } from 'tana/data'
+
+export async function Get(req: Request) {
+  const start = Number(block.gasUsed)
+  await data.set('small', 'x')
+  const afterSmall = Number(block.gasUsed)
+  await data.set('large', 'x'.repeat(5000))
+  const afterLarge = Number(block.gasUsed)
+
+  return Response.json({
+    gasLimit: Number(block.gasLimit),
+    smallWrite: afterSmall - start,
+    largeWrite: afterLarge - afterSmall,
+    gasUsed: afterLarge,
+  })
+}
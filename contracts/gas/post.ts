@@ -0,0 +1,17 @@
+// Writes until the run is out of gas: aborted even though the error is caught
+import { Request, Response } from 'tana/net'
+import { data } from 'tana/data'
+
+export async function Post(req: Request, body: any) {
+  const value = 'x'.repeat(10000)
+  let writes = 0
+  while (true) {
+    try {
+      await data.set('key' + (writes % 10), value)
+      writes++
+    } catch (e) {
+      // Never reached for "Out of gas": the isolate is terminated instead
+    }
+  }
+  return Response.json({ writes })
+}
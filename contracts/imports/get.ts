@@ -0,0 +1,21 @@
+// Exercises the import forms the ES module loader supports
+import type { Request } from 'tana/net'
+import {
+  Response,
+} from 'tana:net'
+import * as core from 'tana/core'
+import data from 'tana:data'
+
+export async function Get(req: Request) {
+  core.console.log('imports contract:', req.path)
+
+  const visits = ((await data.data.get('visits')) || 0) + 1
+  await data.data.set('visits', visits)
+  await data.data.commit()
+
+  return Response.json({
+    message: 'multi-line, namespace, default and tana: imports all resolved',
+    tana: core.version.tana,
+    visits,
+  })
+}
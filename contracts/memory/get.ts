@@ -0,0 +1,10 @@
+// Allocates until the isolate nears TANA_HEAP_MB: the run is terminated with "Memory limit exceeded"
+import { Response } from 'tana/net'
+
+export function Get(req: Request) {
+  const chunks: string[][] = []
+  while (true) {
+    chunks.push(new Array(1_000_000).fill('tana'))
+  }
+  return Response.json({ chunks: chunks.length })
+}
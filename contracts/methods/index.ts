@@ -0,0 +1,14 @@
+// Method dispatch: GET/PUT/DELETE are handled, PATCH etc. get a 405 with Allow: GET, PUT, DELETE, HEAD
+import { Request, Response } from 'tana/net'
+
+export function Get(req: Request) {
+  return Response.json({ method: req.method, path: req.path })
+}
+
+export function Put(req: Request, body: any) {
+  return Response.json({ method: req.method, path: req.path, body })
+}
+
+export function Delete(req: Request) {
+  return Response.json({ method: req.method, deleted: req.path })
+}
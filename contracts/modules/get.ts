@@ -11,12 +11,12 @@ export async function Get(req: Request) {
   console.log('✓ tana/core works')
 
   // Test tana/block (blockchain context - no external API calls)
-  const height = Number(block.getHeight())
-  const timestamp = block.getTimestamp()
-  const hash = block.getHash()
-  const executor = block.getExecutor()
-  const gasLimit = Number(block.getGasLimit())
-  const gasUsed = Number(block.getGasUsed())
+  const height = Number(block.height)
+  const timestamp = block.timestamp
+  const hash = block.hash
+  const executor = block.executor
+  const gasLimit = Number(block.gasLimit)
+  const gasUsed = Number(block.gasUsed)
 
   console.log('✓ tana/block works', { height, executor })
 
@@ -29,9 +29,16 @@ export async function Get(req: Request) {
   const keys = await data.keys()
   console.log('✓ tana/data works, keys:', keys)
 
-  // Test tana/tx (transaction staging)
-  tx.transfer('alice', 'bob', 100, 'USD')
-  tx.setBalance('charlie', 500, 'USD')
+  // Test tana/tx (transaction staging): transfers can only debit the caller,
+  // and setBalance is reserved for system contracts
+  if (executor) {
+    tx.transfer(executor, 'bob', 100, 'USD')
+  }
+  try {
+    tx.setBalance('charlie', 500, 'USD')
+  } catch (error) {
+    console.log('✓ tana/tx refused setBalance:', error.message)
+  }
   const changes = tx.getChanges()
 
   console.log('✓ tana/tx works, changes:', changes)
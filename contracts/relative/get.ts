@@ -0,0 +1,7 @@
+// Relative imports resolve inside contracts/relative/ only
+import { Response } from 'tana/net'
+import { greeting } from './lib/format'
+
+export function Get(req: Request) {
+  return Response.json({ message: greeting('tana-edge') })
+}
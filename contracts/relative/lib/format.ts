@@ -0,0 +1,4 @@
+// Shared helper imported by get.ts and post.ts
+export function greeting(name: string): string {
+  return `Hello, ${name}!`
+}
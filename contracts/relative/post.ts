@@ -0,0 +1,6 @@
+import { Response } from 'tana/net'
+import { greeting } from './lib/format.js'
+
+export function Post(req: Request, body: { name?: string }) {
+  return Response.json({ message: greeting(body?.name || 'anonymous') })
+}
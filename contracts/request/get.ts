@@ -0,0 +1,13 @@
+// Echoes the request context: try GET /request/some/path?x=1 with an X-Tana-Test header
+import { Request, Response } from 'tana/net'
+
+export function Get(req: Request) {
+  return Response.json({
+    path: req.path,
+    method: req.method,
+    query: req.query,
+    headers: req.headers,
+    params: req.params,
+    ip: req.ip,
+  })
+}
@@ -0,0 +1,23 @@
+// Response types: GET /response?type=text|html|binary|created (JSON by default)
+import { Request, Response } from 'tana/net'
+
+export function Get(req: Request) {
+  switch (req.query.type) {
+    case 'text':
+      return Response.text('plain text')
+    case 'html':
+      return Response.html('<h1>hello from tana-edge</h1>')
+    case 'binary':
+      // "tana" as bytes
+      return Response.binary(new Uint8Array([0x74, 0x61, 0x6e, 0x61]))
+    case 'created':
+      // Set-Cookie is dropped by the edge, X-Tana-Example is kept
+      return new Response(201, { created: true }, {
+        'Content-Type': 'application/json',
+        'X-Tana-Example': 'yes',
+        'Set-Cookie': 'session=1',
+      })
+    default:
+      return Response.json({ type: 'json' })
+  }
+}
@@ -0,0 +1,31 @@
+// Streamed responses: GET /stream (text chunks) or GET /stream?sse=1 (Server-Sent Events)
+// curl -N shows the chunks as they arrive
+import { Request, Response } from 'tana/net'
+import { data } from 'tana/data'
+
+async function* progress(steps: number) {
+  for (let step = 1; step <= steps; step++) {
+    // Each step waits on an op, so chunks go out while the run is still going
+    await data.set('stream:step', String(step))
+    yield { step, of: steps }
+  }
+}
+
+export function Get(req: Request) {
+  const steps = Number(req.query.steps || 5)
+
+  if (req.query.sse) {
+    return Response.sse((async function* () {
+      for await (const update of progress(steps)) {
+        yield { event: 'progress', id: update.step, data: update }
+      }
+      yield { event: 'done', data: 'ok' }
+    })())
+  }
+
+  return Response.stream((async function* () {
+    for await (const update of progress(steps)) {
+      yield `step ${update.step}/${update.of}\n`
+    }
+  })())
+}
@@ -5,10 +5,10 @@ import { block } from "tana/block";
 async function Get(req) {
   console.log("GET request received:", req.path);
   try {
-    const height = block.getHeight();
-    const timestamp = block.getTimestamp();
-    const hash = block.getHash();
-    const gasUsed = block.getGasUsed();
+    const height = block.height;
+    const timestamp = block.timestamp;
+    const hash = block.hash;
+    const gasUsed = block.gasUsed;
     let balance = null;
     try {
       balance = await block.getBalance("usr_alice", "USD");
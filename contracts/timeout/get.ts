@@ -0,0 +1,10 @@
+// Never returns: the run is terminated at TANA_BUSY_TIMEOUT_MS and answered with a 504
+import { Response } from 'tana/net'
+
+export function Get(req: Request) {
+  let spins = 0
+  while (true) {
+    spins++
+  }
+  return Response.json({ spins })
+}
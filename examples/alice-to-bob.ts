@@ -1,5 +1,6 @@
 // Alice to Bob Transfer Test
 // Simple sanity check for testing transfers between users
+// Transfers debit Alice, so run it as her: tana-runtime --executor <alice's user id> examples/alice-to-bob.ts
 
 import { console } from 'tana:core'
 import { block } from 'tana:block'
@@ -1,5 +1,6 @@
 // Comprehensive Test Suite
 // Tests multiple blockchain operations for sanity checking
+// Transfers debit Alice, so run it as her: tana-runtime --executor <alice's user id> examples/test-suite.ts
 
 import { console } from 'tana:core'
 import { block } from 'tana:block'
@@ -0,0 +1,2 @@
+-- Look up settled contract batches by idempotency key (POST /transactions/batch)
+CREATE INDEX IF NOT EXISTS "idx_transactions_idempotency_key" ON "transactions"(("contract_input"->>'idempotencyKey'));
@@ -5,7 +5,7 @@
 import { Hono } from 'hono'
 import { zValidator } from '@hono/zod-validator'
 import * as transactionService from '../../transactions'
-import { createTransactionSchema, confirmTransactionSchema } from '../schemas'
+import { createTransactionSchema, confirmTransactionSchema, settleChangesSchema } from '../schemas'
 
 const app = new Hono()
 
@@ -36,6 +36,34 @@ app.post('/', zValidator('json', createTransactionSchema), async (c) => {
   }
 })
 
+// POST /transactions/batch - Settle a contract run's changes (all or nothing)
+// A batch already settled under its idempotencyKey returns its transactions with 200
+app.post('/batch', zValidator('json', settleChangesSchema), async (c) => {
+  const body = c.req.valid('json')
+
+  try {
+    const { transactions, replayed } = await transactionService.settleChanges(body)
+    return c.json({ transactions }, replayed ? 200 : 201)
+  } catch (error: any) {
+    if (error instanceof transactionService.SettlementError) {
+      return c.json({ error: error.message, index: error.index }, 409)
+    }
+    throw error
+  }
+})
+
+// GET /transactions/batch/:key - Transactions settled under an idempotency key
+app.get('/batch/:key', async (c) => {
+  const { key } = c.req.param()
+  const transactions = await transactionService.getSettledBatch(key)
+
+  if (transactions.length === 0) {
+    return c.json({ error: 'Batch not found' }, 404)
+  }
+
+  return c.json({ transactions })
+})
+
 // GET /transactions/:id - Get transaction
 app.get('/:id', async (c) => {
   const { id } = c.req.param()
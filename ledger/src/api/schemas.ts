@@ -80,6 +80,35 @@ export const confirmTransactionSchema = z.object({
   blockId: z.string().uuid().optional(),
 })
 
+const contractChangeAmount = z.string().regex(/^\d+(\.\d+)?$/, 'Amount must be a valid decimal number')
+
+// tx.transfer() / tx.setBalance() changes of one contract run, settled together
+export const settleChangesSchema = z.object({
+  contractId: z.string().min(1),
+  executor: z.string().optional(),
+  idempotencyKey: z.string().min(1).optional(),
+  changes: z
+    .array(
+      z.discriminatedUnion('type', [
+        z.object({
+          type: z.literal('transfer'),
+          from: z.string().uuid(),
+          to: z.string().uuid(),
+          amount: contractChangeAmount,
+          currency: z.string().min(1).max(10).toUpperCase(),
+        }),
+        z.object({
+          type: z.literal('balance_update'),
+          userId: z.string().uuid(),
+          amount: contractChangeAmount,
+          currency: z.string().min(1).max(10).toUpperCase(),
+        }),
+      ])
+    )
+    .min(1)
+    .max(1000),
+})
+
 // ============================================================================
 // BALANCE SCHEMAS
 // ============================================================================
@@ -4,8 +4,8 @@
  * Create and manage blockchain transactions
  */
 
-import { eq } from 'drizzle-orm'
-import { db, transactions } from '../db'
+import { and, eq, sql } from 'drizzle-orm'
+import { db, transactions, balances, currencies } from '../db'
 import { transferBalance, getBalance } from '../balances'
 
 export interface CreateTransactionInput {
@@ -24,6 +24,31 @@ export interface ConfirmTransactionInput {
   blockId?: string
 }
 
+export type ContractChange =
+  | { type: 'transfer'; from: string; to: string; amount: string; currency: string }
+  | { type: 'balance_update'; userId: string; amount: string; currency: string }
+
+export interface SettleChangesInput {
+  contractId: string // Contract name as the runtime knows it
+  executor?: string
+  idempotencyKey?: string // Batch id; a batch with a settled key isn't applied again
+  changes: ContractChange[]
+}
+
+/**
+ * A change that couldn't be applied; `index` is its position in the batch
+ */
+export class SettlementError extends Error {
+  constructor(message: string, public index: number) {
+    super(message)
+  }
+}
+
+// Contract runs aren't signed by the executor (yet)
+const CONTRACT_SETTLEMENT_SIGNATURE = 'contract_settlement'
+
+type DbTransaction = Parameters<Parameters<typeof db.transaction>[0]>[0]
+
 /**
  * Create a new transaction
  */
@@ -173,3 +198,144 @@ export async function getAllTransactions(limit = 100, offset = 0) {
 export async function getPendingTransactions(limit = 100) {
   return await db.select().from(transactions).where(eq(transactions.status, 'pending')).limit(limit)
 }
+
+/**
+ * Settle the tx changes of a contract run atomically
+ *
+ * All changes are applied in one database transaction and recorded as
+ * confirmed transactions, in order. If any change fails (unknown currency,
+ * insufficient balance, ...) the whole batch is rolled back.
+ *
+ * With an idempotency key, a batch that was already settled under that key
+ * isn't applied again: its transactions are returned with `replayed` set.
+ */
+export async function settleChanges(input: SettleChangesInput) {
+  return await db.transaction(async (tx) => {
+    if (input.idempotencyKey) {
+      // Batches with the same key settle one at a time
+      await tx.execute(sql`select pg_advisory_xact_lock(hashtext(${input.idempotencyKey}))`)
+      const existing = await settledBatch(tx, input.idempotencyKey)
+      if (existing.length > 0) {
+        return { transactions: existing, replayed: true }
+      }
+    }
+
+    const settled = []
+    for (const [index, change] of input.changes.entries()) {
+      try {
+        settled.push(await applyChange(tx, input, change, index))
+      } catch (error: any) {
+        throw new SettlementError(error.message, index)
+      }
+    }
+    return { transactions: settled, replayed: false }
+  })
+}
+
+/**
+ * Transactions settled under an idempotency key, in batch order (empty if none)
+ *
+ * Waits for a settlement of the same key that is still in progress.
+ */
+export async function getSettledBatch(idempotencyKey: string) {
+  return await db.transaction(async (tx) => {
+    await tx.execute(sql`select pg_advisory_xact_lock(hashtext(${idempotencyKey}))`)
+    return await settledBatch(tx, idempotencyKey)
+  })
+}
+
+async function settledBatch(tx: DbTransaction, idempotencyKey: string) {
+  return await tx
+    .select()
+    .from(transactions)
+    .where(sql`${transactions.contractInput}->>'idempotencyKey' = ${idempotencyKey}`)
+    .orderBy(sql`(${transactions.contractInput}->>'index')::int`)
+}
+
+async function applyChange(tx: DbTransaction, input: SettleChangesInput, change: ContractChange, index: number) {
+  const currencyCode = change.currency.toUpperCase()
+  const [currency] = await tx.select().from(currencies).where(eq(currencies.code, currencyCode)).limit(1)
+  if (!currency) {
+    throw new Error(`Currency ${currencyCode} not found`)
+  }
+
+  let from: string
+  let to: string
+  if (change.type === 'transfer') {
+    const sender = await lockBalance(tx, change.from, currencyCode)
+    const available = sender ? parseFloat(sender.amount) : 0
+    if (!sender || available < parseFloat(change.amount)) {
+      throw new Error(`Insufficient balance: ${available} ${currencyCode}`)
+    }
+
+    await tx
+      .update(balances)
+      .set({ amount: sql`${balances.amount} - ${change.amount}`, updatedAt: new Date() })
+      .where(eq(balances.id, sender.id))
+    await writeBalance(tx, change.to, currencyCode, sql`${balances.amount} + ${change.amount}`, change.amount)
+
+    from = change.from
+    to = change.to
+  } else {
+    await writeBalance(tx, change.userId, currencyCode, change.amount, change.amount)
+
+    from = change.userId
+    to = change.userId
+  }
+
+  // Balance updates have no transaction type of their own; they are recorded
+  // as contract calls with the change in contractInput (along with the batch's
+  // idempotency key and the change's index in it, see settledBatch)
+  const [record] = await tx
+    .insert(transactions)
+    .values({
+      from,
+      to,
+      amount: change.amount,
+      currencyCode,
+      type: change.type === 'transfer' ? 'transfer' : 'contract_call',
+      contractInput: {
+        contractId: input.contractId,
+        executor: input.executor,
+        idempotencyKey: input.idempotencyKey,
+        index,
+        change,
+      } as any,
+      signature: CONTRACT_SETTLEMENT_SIGNATURE,
+      status: 'confirmed',
+      confirmedAt: new Date(),
+    })
+    .returning()
+
+  return record
+}
+
+// A user's balance row, locked until the settlement commits
+async function lockBalance(tx: DbTransaction, ownerId: string, currencyCode: string) {
+  const [balance] = await tx
+    .select()
+    .from(balances)
+    .where(
+      and(eq(balances.ownerId, ownerId), eq(balances.ownerType, 'user'), eq(balances.currencyCode, currencyCode))
+    )
+    .limit(1)
+    .for('update')
+
+  return balance || null
+}
+
+// Update a user's balance to `amount`, or create it with `initial`
+async function writeBalance(
+  tx: DbTransaction,
+  ownerId: string,
+  currencyCode: string,
+  amount: string | ReturnType<typeof sql>,
+  initial: string
+) {
+  const existing = await lockBalance(tx, ownerId, currencyCode)
+  if (existing) {
+    await tx.update(balances).set({ amount, updatedAt: new Date() }).where(eq(balances.id, existing.id))
+  } else {
+    await tx.insert(balances).values({ ownerId, ownerType: 'user', currencyCode, amount: initial })
+  }
+}
@@ -1,5 +1,42 @@
 // build.rs
+use std::env;
 use std::fs;
+use std::path::PathBuf;
+
+// Compiled here too so the snapshot registers exactly the ops the binary does
+// (the sources are shared with tana-edge, see src/main.rs)
+#[allow(dead_code)]
+#[path = "../tana-edge/src/storage.rs"]
+mod storage;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/gas.rs"]
+mod gas;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/context.rs"]
+mod context;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/egress.rs"]
+mod egress;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/fetch.rs"]
+mod fetch;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/fetch_cache.rs"]
+mod fetch_cache;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/request_body.rs"]
+mod request_body;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/stream.rs"]
+mod stream;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/ledger.rs"]
+mod ledger;
+#[allow(dead_code)]
+#[path = "../tana-edge/src/ops.rs"]
+mod ops;
+#[path = "../tana-edge/src/bootstrap.rs"]
+mod bootstrap;
 
 fn main() {
     // super simple: read Cargo.lock as text
@@ -26,4 +63,69 @@ fn main() {
     // pass to rustc
     println!("cargo:rustc-env=DENO_CORE_VERSION={}", deno_core_ver);
     println!("cargo:rustc-env=V8_VERSION={}", v8_ver);
-}
\ No newline at end of file
+
+    build_snapshot(deno_core_ver, v8_ver);
+}
+
+// Bake typescript.js + the tana bootstrap into a V8 startup snapshot
+// Writes an empty file when typescript.js is missing; the binary then uses the cold path
+fn build_snapshot(deno_core_ver: &str, v8_ver: &str) {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-changed=typescript.js");
+    println!("cargo:rerun-if-changed=tana-globals.ts");
+    println!("cargo:rerun-if-changed=../tana-edge/src/storage.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/gas.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/context.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/egress.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/fetch.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/fetch_cache.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/request_body.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/stream.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/ledger.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/ops.rs");
+    println!("cargo:rerun-if-changed=../tana-edge/src/bootstrap.rs");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let snapshot_path = out_dir.join("TANA_SNAPSHOT.bin");
+
+    let (ts_src, tana_globals) = match (
+        fs::read_to_string("typescript.js"),
+        fs::read_to_string("tana-globals.ts"),
+    ) {
+        (Ok(ts_src), Ok(tana_globals)) => (ts_src, tana_globals),
+        _ => {
+            println!("cargo:warning=typescript.js or tana-globals.ts not found, building without startup snapshot");
+            fs::write(&snapshot_path, []).expect("Failed to write empty snapshot");
+            return;
+        },
+    };
+
+    let bootstrap_globals = bootstrap::full_bootstrap(
+        &tana_globals,
+        env!("CARGO_PKG_VERSION"),
+        deno_core_ver,
+        v8_ver,
+    );
+
+    let output = deno_core::snapshot::create_snapshot(
+        deno_core::snapshot::CreateSnapshotOptions {
+            cargo_manifest_dir: env!("CARGO_MANIFEST_DIR"),
+            startup_snapshot: None,
+            skip_op_registration: false,
+            extensions: vec![ops::tana_extension()],
+            extension_transpiler: None,
+            with_runtime_cb: Some(Box::new(move |runtime| {
+                runtime
+                    .execute_script("typescript.js", deno_core::ModuleCodeString::from(ts_src.clone()))
+                    .expect("Failed to load TypeScript into snapshot");
+                runtime
+                    .execute_script("tana-bootstrap.js", deno_core::ModuleCodeString::from(bootstrap_globals.clone()))
+                    .expect("Failed to bootstrap snapshot");
+            })),
+        },
+        None,
+    )
+    .expect("Failed to create startup snapshot");
+
+    fs::write(&snapshot_path, output.output).expect("Failed to write snapshot");
+}
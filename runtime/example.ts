@@ -46,9 +46,13 @@ console.log(`Found ${validUsers.length} users from batch query`)
 // propose state changes based on current blockchain state
 console.log("\n--- Transaction Execution ---")
 
-// propose a transfer from executor to treasury
-tx.transfer(block.executor, 'treasury', 5, 'USD')
-console.log("Proposed transfer: 5 USD to treasury")
+// propose a transfer from executor to treasury (contracts can only debit their caller)
+if (block.executor) {
+  tx.transfer(block.executor, 'treasury', 5, 'USD')
+  console.log("Proposed transfer: 5 USD to treasury")
+} else {
+  console.log("No executor (run with --executor <user id>), skipping the transfer")
+}
 
 // store transaction in contract state
 await data.set('lastTransfer', {
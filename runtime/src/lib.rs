@@ -9,13 +9,20 @@ use wee_alloc::WeeAlloc;
 static ALLOC: WeeAlloc = WeeAlloc::INIT;
 
 use deno_core::op2;
-use deno_core::{Extension, JsRuntime, ModuleCodeString, RuntimeOptions};
+use deno_core::{Extension, JsRuntime, ModuleCodeString, OpState, RuntimeOptions};
+
+// Same gas schedule and meter as the CLI (shared with tana-edge)
+#[allow(dead_code)]
+#[path = "../../tana-edge/src/context.rs"]
+mod context;
+#[allow(dead_code)]
+#[path = "../../tana-edge/src/gas.rs"]
+mod gas;
 
 // Output capture for WASM
 thread_local! {
     static OUTPUT: RefCell<Vec<String>> = RefCell::new(Vec::new());
     static ERRORS: RefCell<Vec<String>> = RefCell::new(Vec::new());
-    static GAS_USED: RefCell<u64> = RefCell::new(0);
     static EXECUTION_SUCCESS: RefCell<bool> = RefCell::new(true);
 }
 
@@ -38,18 +45,10 @@ fn op_sum(#[serde] nums: Vec<f64>) -> Result<f64, deno_error::JsErrorBox> {
     Ok(nums.iter().sum())
 }
 
+// Compute gas: execute_with_validity injects __tanaGas(n) into every block
 #[op2(fast)]
-fn op_track_gas(#[bigint] amount: u64) {
-    GAS_USED.with(|gas| {
-        *gas.borrow_mut() += amount;
-    });
-}
-
-#[op2(fast)]
-fn op_mark_failure() {
-    EXECUTION_SUCCESS.with(|success| {
-        *success.borrow_mut() = false;
-    });
+fn op_gas_statements(state: &mut OpState, #[smi] statements: u32) -> Result<(), deno_error::JsErrorBox> {
+    gas::charge(state, gas::compute_gas(statements as u64))
 }
 
 #[wasm_bindgen]
@@ -69,8 +68,7 @@ impl TanaRuntime {
         const OP_SUM: deno_core::OpDecl = op_sum();
         const OP_PRINT_STDOUT: deno_core::OpDecl = op_print_stdout();
         const OP_PRINT_STDERR: deno_core::OpDecl = op_print_stderr();
-        const OP_TRACK_GAS: deno_core::OpDecl = op_track_gas();
-        const OP_MARK_FAILURE: deno_core::OpDecl = op_mark_failure();
+        const OP_GAS_STATEMENTS: deno_core::OpDecl = op_gas_statements();
 
         let ext = Extension {
             name: "tana_ext",
@@ -78,8 +76,7 @@ impl TanaRuntime {
                 OP_SUM,
                 OP_PRINT_STDOUT,
                 OP_PRINT_STDERR,
-                OP_TRACK_GAS,
-                OP_MARK_FAILURE
+                OP_GAS_STATEMENTS
             ]),
             ..Default::default()
         };
@@ -173,6 +170,7 @@ impl TanaRuntime {
         self.runtime
             .execute_script("tana-bootstrap.js", ModuleCodeString::from(bootstrap_globals))
             .map_err(|e| JsValue::from_str(&format!("Bootstrap failed: {:?}", e)))?;
+        gas::install_hook(&mut self.runtime).map_err(|e| JsValue::from_str(&e))?;
 
         Ok(())
     }
@@ -233,30 +231,18 @@ impl TanaRuntime {
         // Clear previous state
         OUTPUT.with(|o| o.borrow_mut().clear());
         ERRORS.with(|e| e.borrow_mut().clear());
-        GAS_USED.with(|g| *g.borrow_mut() = 0);
         EXECUTION_SUCCESS.with(|s| *s.borrow_mut() = true);
 
-        // Base gas for any execution
-        GAS_USED.with(|g| *g.borrow_mut() = 1000);
+        // Metered like a CLI run: base cost, then per op and per statement (see gas.rs)
+        let meter = gas::install_meter(&mut self.runtime, context::DEFAULT_GAS_LIMIT);
 
-        let runner = format!(
+        // Transpile with metering injected; the output runs as its own script, not
+        // through eval, so all of it is metered
+        let transpile = format!(
             r#"
-            let src = {user_src};
-
-            // Track gas for operations
-            const trackGas = (amount) => {{
-                if (globalThis.__tanaCore) {{
-                    globalThis.__tanaCore.ops.op_track_gas(amount);
-                }}
-            }};
+            (() => {{
+                let src = {user_src};
 
-            const markFailure = () => {{
-                if (globalThis.__tanaCore) {{
-                    globalThis.__tanaCore.ops.op_mark_failure();
-                }}
-            }};
-
-            try {{
                 // line-by-line import rewriter
                 src = src
                   .split("\n")
@@ -269,45 +255,55 @@ impl TanaRuntime {
                   }})
                   .join("\n");
 
-                trackGas(500); // Gas for transpilation
-
-                const out = ts.transpileModule(src, {{
+                return ts.transpileModule(src, {{
                   compilerOptions: {{
                     target: "ES2020",
                     module: ts.ModuleKind.ESNext
-                  }}
-                }});
-
-                trackGas(1000); // Gas for eval
-                (0, eval)(out.outputText);
-
-            }} catch (error) {{
-                markFailure();
-                globalThis.__tanaCore.ops.op_print_stderr(
-                    "Execution error: " + error.message
-                );
-            }}
+                  }},
+                  transformers: {{ after: [{meter}] }}
+                }}).outputText;
+            }})()
             "#,
             user_src = serde_json::to_string(user_code).unwrap(),
+            meter = gas::METER_TRANSFORMER,
         );
 
-        self.runtime
-            .execute_script("run-user.ts", ModuleCodeString::from(runner))
+        let transpiled = self
+            .runtime
+            .execute_script("transpile-user.ts", ModuleCodeString::from(transpile))
             .map_err(|e| {
                 EXECUTION_SUCCESS.with(|s| *s.borrow_mut() = false);
-                JsValue::from_str(&format!("Execution error: {:?}", e))
+                JsValue::from_str(&format!("Transpile error: {:?}", e))
             })?;
+        let code = {
+            let scope = &mut self.runtime.handle_scope();
+            let local = deno_core::v8::Local::new(scope, transpiled);
+            local.to_rust_string_lossy(scope)
+        };
+
+        if let Err(e) = self.runtime.execute_script("run-user.js", ModuleCodeString::from(code)) {
+            EXECUTION_SUCCESS.with(|s| *s.borrow_mut() = false);
+            let message = if meter.is_exhausted() {
+                format!("Out of gas (limit {})", meter.limit())
+            } else {
+                e.to_string()
+            };
+            ERRORS.with(|errors| errors.borrow_mut().push(format!("Execution error: {}", message)));
+        }
+        // Out of gas terminated the isolate; let the next execution run
+        if meter.is_exhausted() {
+            self.runtime.v8_isolate().cancel_terminate_execution();
+        }
 
         // Collect results
         let stdout = OUTPUT.with(|o| o.borrow().join(""));
         let stderr = ERRORS.with(|e| e.borrow().join(""));
-        let gas_used = GAS_USED.with(|g| *g.borrow());
         let success = EXECUTION_SUCCESS.with(|s| *s.borrow());
 
         // Return JSON result
         let result = serde_json::json!({
             "success": success,
-            "gas_used": gas_used,
+            "gas_used": meter.used(),
             "output": stdout,
             "error": if stderr.is_empty() { None } else { Some(stderr) }
         });
@@ -6,7 +6,7 @@ import { fetch } from 'tana:utils'
 
 console.log("Testing fetch API...\n")
 
-// Test 1: Fetch from whitelisted domain (pokeapi.co)
+// Test 1: Fetch from an allowed domain (run with TANA_EGRESS_ALLOW=pokeapi.co)
 try {
   const response = await fetch('https://pokeapi.co/api/v2/pokemon/pikachu')
   const data = await response.json()
@@ -15,13 +15,40 @@ try {
   console.error("✗ Fetch failed:", error.message)
 }
 
-// Test 2: Try to fetch from non-whitelisted domain (should fail)
-console.log("\nTesting domain whitelist...")
+// Test 2: Try to fetch from a domain outside the egress policy (should fail)
+console.log("\nTesting egress policy...")
 try {
   await fetch('https://google.com')
-  console.error("✗ Whitelist bypass - this should have been blocked!")
+  console.error("✗ Egress policy bypass - this should have been blocked!")
 } catch (error) {
-  console.log("✓ Whitelist working:", error.message)
+  console.log("✓ Egress policy working:", error.message)
+}
+
+// Test 3: Real status and headers (404 must not look like success)
+console.log("\nTesting response status...")
+try {
+  const response = await fetch('https://pokeapi.co/api/v2/pokemon/not-a-pokemon')
+  if (response.ok || response.status !== 404) {
+    console.error("✗ Expected 404, got", response.status)
+  } else {
+    console.log("✓ Status:", response.status, response.statusText, "- content-type:", response.headers.get('content-type'))
+  }
+} catch (error) {
+  console.error("✗ Fetch failed:", error.message)
+}
+
+// Test 4: RequestInit (method, headers, timeout) and binary body
+console.log("\nTesting RequestInit...")
+try {
+  const response = await fetch('https://pokeapi.co/api/v2/pokemon/ditto', {
+    method: 'GET',
+    headers: { 'Accept': 'application/json' },
+    timeout: 5000,
+  })
+  const bytes = await response.arrayBuffer()
+  console.log("✓ RequestInit:", response.status, "-", bytes.byteLength, "bytes")
+} catch (error) {
+  console.error("✗ Fetch failed:", error.message)
 }
 
 console.log("\nFeature parity test complete!")
@@ -0,0 +1,361 @@
+// Full tana bootstrap: tana modules, user globals from tana-globals.ts and the import shim
+//
+// Shared with build.rs, which runs this script once (after typescript.js) to bake the
+// result into the V8 startup snapshot. Deno is deliberately left in place here:
+// deno_core binds ops through Deno.core when a runtime is restored from the snapshot,
+// so SANDBOX_SCRIPT removes it only after the runtime has been created.
+
+// Requires the TypeScript compiler (ts) to be loaded first
+pub fn full_bootstrap(
+    tana_globals: &str,
+    tana_version: &str,
+    deno_core_version: &str,
+    v8_version: &str,
+) -> String {
+    format!(
+        r#"
+        globalThis.__tanaCore = globalThis.Deno?.core;
+
+        const tanaModules = Object.create(null);
+
+        // tana/core module
+        tanaModules["tana/core"] = {{
+            console: {{
+                log(...args) {{
+                    if (globalThis.__tanaCore) {{
+                        const msg = args.map(v => {{
+                            if (typeof v === 'object') {{
+                                try {{ return JSON.stringify(v, null, 2); }}
+                                catch {{ return String(v); }}
+                            }}
+                            return String(v);
+                        }}).join(' ');
+                        globalThis.__tanaCore.print(msg + "\n");
+                    }}
+                }},
+                error(...args) {{
+                    if (globalThis.__tanaCore) {{
+                        const msg = args.map(v => {{
+                            if (typeof v === 'object') {{
+                                try {{ return JSON.stringify(v, null, 2); }}
+                                catch {{ return String(v); }}
+                            }}
+                            return String(v);
+                        }}).join(' ');
+                        globalThis.__tanaCore.ops.op_print_stderr(msg + "\n");
+                    }}
+                }},
+            }},
+            version: {{
+                tana: "{tana_version}",
+                deno_core: "{deno_core_version}",
+                v8: "{v8_version}",
+            }},
+        }};
+
+        // tana/net module (NEW - for edge requests/responses)
+        tanaModules["tana/net"] = {{
+            Request: class Request {{
+                constructor(data) {{
+                    this.path = data?.path || '/';
+                    this.method = data?.method || 'GET';
+                    this.query = data?.query || {{}};
+                    this.headers = data?.headers || {{}};
+                    this.params = data?.params || {{}};
+                    this.ip = data?.ip || '127.0.0.1';
+                }}
+
+                // Raw body of the current request, parsed on demand
+                async arrayBuffer() {{
+                    const bytes = globalThis.__tanaCore.ops.op_request_body();
+                    return bytes.buffer.slice(bytes.byteOffset, bytes.byteOffset + bytes.byteLength);
+                }}
+
+                async text() {{
+                    return globalThis.__tanaCore.ops.op_request_text();
+                }}
+
+                async json() {{
+                    return JSON.parse(await this.text());
+                }}
+
+                // {{ name: value }} (repeated names give arrays); files are {{ filename, type, data }}
+                async formData() {{
+                    const form = Object.create(null);
+                    for (const [name, value] of globalThis.__tanaCore.ops.op_request_form()) {{
+                        form[name] = Object.hasOwn(form, name) ? [].concat(form[name], value) : value;
+                    }}
+                    return form;
+                }}
+            }},
+            Response: class Response {{
+                constructor(status, body, headers) {{
+                    this.status = status || 200;
+                    this.body = body || null;
+                    this.headers = headers || {{}};
+                }}
+
+                static json(data, status = 200) {{
+                    return new Response(status, data, {{ 'Content-Type': 'application/json' }});
+                }}
+
+                static text(data, status = 200) {{
+                    return new Response(status, data, {{ 'Content-Type': 'text/plain' }});
+                }}
+
+                static html(data, status = 200) {{
+                    return new Response(status, data, {{ 'Content-Type': 'text/html; charset=utf-8' }});
+                }}
+
+                // Uint8Array / ArrayBuffer (or a base64 string)
+                static binary(data, contentType = 'application/octet-stream', status = 200) {{
+                    return new Response(status, data, {{ 'Content-Type': contentType }});
+                }}
+
+                // ReadableStream or (async) iterator of strings / Uint8Arrays, streamed as produced
+                static stream(body, contentType = 'text/plain; charset=utf-8', status = 200) {{
+                    return new Response(status, body, {{ 'Content-Type': contentType }});
+                }}
+
+                // Server-Sent Events from an (async) iterator of data values or {{ event, id, retry, data }}
+                static sse(events, status = 200) {{
+                    const encode = async function* () {{
+                        for await (const event of events) {{
+                            const message = event !== null && typeof event === 'object' && 'data' in event ? event : {{ data: event }};
+                            let frame = '';
+                            if (message.event !== undefined) frame += 'event: ' + message.event + '\n';
+                            if (message.id !== undefined) frame += 'id: ' + message.id + '\n';
+                            if (message.retry !== undefined) frame += 'retry: ' + message.retry + '\n';
+                            const data = typeof message.data === 'string' ? message.data : JSON.stringify(message.data);
+                            for (const line of data.split('\n')) frame += 'data: ' + line + '\n';
+                            yield frame + '\n';
+                        }}
+                    }};
+                    return new Response(status, encode(), {{
+                        'Content-Type': 'text/event-stream',
+                        'Cache-Control': 'no-cache',
+                    }});
+                }}
+            }}
+        }};
+
+        // tana/block module (blockchain queries)
+        tanaModules["tana/block"] = {{
+            block: {{
+                get height() {{
+                    return globalThis.__tanaCore.ops.op_block_get_height();
+                }},
+                get timestamp() {{
+                    return globalThis.__tanaCore.ops.op_block_get_timestamp();
+                }},
+                get hash() {{
+                    return globalThis.__tanaCore.ops.op_block_get_hash();
+                }},
+                get previousHash() {{
+                    return globalThis.__tanaCore.ops.op_block_get_previous_hash();
+                }},
+                get executor() {{
+                    return globalThis.__tanaCore.ops.op_block_get_executor();
+                }},
+                get contractId() {{
+                    return globalThis.__tanaCore.ops.op_block_get_contract_id();
+                }},
+                get contractAccount() {{
+                    return globalThis.__tanaCore.ops.op_block_get_contract_account();
+                }},
+                get gasLimit() {{
+                    return globalThis.__tanaCore.ops.op_block_get_gas_limit();
+                }},
+                get gasUsed() {{
+                    return globalThis.__tanaCore.ops.op_block_get_gas_used();
+                }},
+                MAX_BATCH_QUERY: 10,
+                async getBalance(userIds, currencyCode) {{
+                    return globalThis.__tanaCore.ops.op_block_get_balance(userIds, currencyCode);
+                }},
+                async getUser(userIds) {{
+                    return globalThis.__tanaCore.ops.op_block_get_user(userIds);
+                }},
+                async getTransaction(txIds) {{
+                    return globalThis.__tanaCore.ops.op_block_get_transaction(txIds);
+                }},
+                async getBlock(heights) {{
+                    return globalThis.__tanaCore.ops.op_block_get_block(heights);
+                }},
+                async getLatestBlock() {{
+                    return globalThis.__tanaCore.ops.op_block_get_latest_block();
+                }},
+            }}
+        }};
+
+        // tana/tx module (transaction staging)
+        tanaModules["tana/tx"] = {{
+            tx: {{
+                transfer(from, to, amount, currency) {{
+                    globalThis.__tanaCore.ops.op_tx_transfer(from, to, amount, currency);
+                }},
+                setBalance(userId, amount, currency) {{
+                    globalThis.__tanaCore.ops.op_tx_set_balance(userId, amount, currency);
+                }},
+                getChanges() {{
+                    return globalThis.__tanaCore.ops.op_tx_get_changes();
+                }},
+                async execute() {{
+                    return globalThis.__tanaCore.ops.op_tx_execute();
+                }},
+            }}
+        }};
+
+        // tana/utils module (external fetch)
+        tanaModules["tana/utils"] = {{
+            async fetch(input, init = {{}}) {{
+                const ops = globalThis.__tanaCore.ops;
+
+                // Normalize RequestInit: headers as [name, value] pairs, text or binary body
+                const source = init.headers || {{}};
+                const headers = (typeof source.entries === 'function' ? Array.from(source.entries()) : Object.entries(source))
+                    .map(([name, value]) => [String(name), String(value)]);
+                let text = null;
+                let bytes = new Uint8Array(0);
+                if (typeof init.body === 'string') {{
+                    text = init.body;
+                }} else if (init.body instanceof ArrayBuffer) {{
+                    bytes = new Uint8Array(init.body);
+                }} else if (ArrayBuffer.isView(init.body)) {{
+                    bytes = new Uint8Array(init.body.buffer, init.body.byteOffset, init.body.byteLength);
+                }} else if (init.body !== undefined && init.body !== null) {{
+                    text = JSON.stringify(init.body);
+                    if (!headers.some(([name]) => name.toLowerCase() === 'content-type')) {{
+                        headers.push(['Content-Type', 'application/json']);
+                    }}
+                }}
+
+                const response = await ops.op_fetch(String(input), {{
+                    method: init.method,
+                    headers,
+                    body: text,
+                    timeout: init.timeout,
+                }}, bytes);
+
+                const body = response.body;
+                const lookup = (name) => response.headers
+                    .filter(([key]) => key === String(name).toLowerCase())
+                    .map(([, value]) => value);
+                return {{
+                    ok: response.status >= 200 && response.status < 300,
+                    status: response.status,
+                    statusText: response.statusText,
+                    url: response.url,
+                    redirected: response.redirected,
+                    headers: {{
+                        get(name) {{
+                            const values = lookup(name);
+                            return values.length ? values.join(', ') : null;
+                        }},
+                        has(name) {{
+                            return lookup(name).length > 0;
+                        }},
+                        entries() {{
+                            return response.headers[Symbol.iterator]();
+                        }},
+                        forEach(callback) {{
+                            response.headers.forEach(([name, value]) => callback(value, name));
+                        }},
+                    }},
+                    async text() {{
+                        return ops.op_decode_utf8(body);
+                    }},
+                    async json() {{
+                        return JSON.parse(ops.op_decode_utf8(body));
+                    }},
+                    async arrayBuffer() {{
+                        return body.buffer.slice(body.byteOffset, body.byteOffset + body.byteLength);
+                    }},
+                }};
+            }}
+        }};
+
+        // tana/data module (key-value storage)
+        tanaModules["tana/data"] = {{
+            data: {{
+                MAX_KEY_SIZE: 256,
+                MAX_VALUE_SIZE: 10240,
+                MAX_TOTAL_SIZE: 102400,
+                MAX_KEYS: 1000,
+                _serialize(value) {{
+                    if (typeof value === 'string') return value;
+                    return JSON.stringify(value, (key, val) => {{
+                        if (typeof val === 'bigint') return val.toString();
+                        return val;
+                    }});
+                }},
+                _deserialize(value) {{
+                    if (value === null) return null;
+                    try {{ return JSON.parse(value); }}
+                    catch {{ return value; }}
+                }},
+                async set(key, value) {{
+                    const serialized = this._serialize(value);
+                    globalThis.__tanaCore.ops.op_data_set(key, serialized);
+                }},
+                async get(key) {{
+                    const value = await globalThis.__tanaCore.ops.op_data_get(key);
+                    return this._deserialize(value);
+                }},
+                async delete(key) {{
+                    globalThis.__tanaCore.ops.op_data_delete(key);
+                }},
+                async has(key) {{
+                    return globalThis.__tanaCore.ops.op_data_has(key);
+                }},
+                async keys(pattern) {{
+                    return globalThis.__tanaCore.ops.op_data_keys(pattern || null);
+                }},
+                async entries() {{
+                    const allKeys = await this.keys();
+                    const result = {{}};
+                    for (const key of allKeys) {{
+                        result[key] = await this.get(key);
+                    }}
+                    return result;
+                }},
+                async clear() {{
+                    await globalThis.__tanaCore.ops.op_data_clear();
+                }},
+                async commit() {{
+                    await globalThis.__tanaCore.ops.op_data_commit();
+                }}
+            }}
+        }};
+
+        // Load user-defined globals
+        (function () {{
+          const src = {tana_src};
+          const out = ts.transpileModule(src, {{
+            compilerOptions: {{
+              target: "ES2020",
+              module: ts.ModuleKind.ESNext
+            }}
+          }});
+          (0, eval)(out.outputText);
+        }})();
+
+        // Import shim
+        globalThis.__tanaImport = function (spec) {{
+          const m = tanaModules[spec];
+          if (!m) throw new Error("unknown tana module: " + spec);
+          return m;
+        }};
+        "#,
+        tana_src = serde_json::to_string(tana_globals).unwrap(),
+        tana_version = tana_version,
+        deno_core_version = deno_core_version,
+        v8_version = v8_version,
+    )
+}
+
+// Runs on every new runtime after bootstrap (cold or snapshot)
+pub const SANDBOX_SCRIPT: &str = r#"
+// Deno.core stays reachable only through __tanaCore
+delete globalThis.Deno;
+"#;
@@ -0,0 +1,239 @@
+// Block context of a run: the block a contract executes in and who runs it
+//
+// Every tana/block context op (block.height, block.hash, block.executor, ...)
+// reads this, so the same context gives the same run. The caller provides it:
+// tana-runtime from --context <file> and per-field flags, tana-edge from
+// TANA_BLOCK_CONTEXT and X-Tana-Block-* request headers (the contract id and
+// account there come from the route, see request.rs).
+//
+// The executor is the verified caller: only the operator (CLI flags, context
+// files) or the gateway (see request.rs in tana-edge) can set it. tana/tx
+// checks it: transfers may only debit the executor or the contract's own
+// account (contractAccount), and only system contracts (TANA_SYSTEM_CONTRACTS,
+// comma-separated contract ids) may call setBalance.
+//
+// Context files are .json or .toml (anything else is read as TOML), every
+// field optional:
+//
+//   height = 42
+//   hash = "0xabc..."
+//   previousHash = "0x123..."
+//   timestamp = 1735689600000   # ms since the epoch
+//   executor = "user_alice"
+//   contractId = "counter"
+//   contractAccount = "..."     # ledger user holding the contract's funds (tana-runtime only)
+//   gasLimit = 1000000
+//
+// Without a timestamp the run uses the wall clock at its start (read once, so
+// every block.timestamp in the run agrees).
+//
+// Compiled into build.rs along with ops.rs, so this file must only depend on
+// crates listed in both [dependencies] and [build-dependencies].
+
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_GAS_LIMIT: u64 = 1_000_000;
+
+// Settable fields by flag / header name (--previous-hash, X-Tana-Block-Previous-Hash)
+pub const FIELDS: &[&str] = &[
+    "height",
+    "hash",
+    "previous-hash",
+    "timestamp",
+    "executor",
+    "contract-id",
+    "contract-account",
+    "gas-limit",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase", deny_unknown_fields)]
+pub struct BlockContext {
+    pub height: u64,
+    pub hash: String,
+    pub previous_hash: Option<String>,
+    pub timestamp: Option<f64>,
+    pub executor: String,
+    pub contract_id: String,
+    pub contract_account: Option<String>,
+    pub gas_limit: u64,
+}
+
+impl Default for BlockContext {
+    fn default() -> Self {
+        BlockContext {
+            height: 0,
+            hash: format!("0x{}", "0".repeat(64)),
+            previous_hash: None,
+            timestamp: None,
+            executor: String::new(),
+            contract_id: String::new(),
+            contract_account: None,
+            gas_limit: DEFAULT_GAS_LIMIT,
+        }
+    }
+}
+
+impl BlockContext {
+    pub fn from_file(path: &str) -> Result<BlockContext, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Failed to read block context {}: {}", path, e))?;
+        if path.ends_with(".json") {
+            serde_json::from_str(&text).map_err(|e| format!("Invalid block context {}: {}", path, e))
+        } else {
+            toml::from_str(&text).map_err(|e| format!("Invalid block context {}: {}", path, e))
+        }
+    }
+
+    // Set one field by its flag / header name (see FIELDS)
+    pub fn set(&mut self, field: &str, value: &str) -> Result<(), String> {
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| format!("{} must be a non-negative integer, got '{}'", field, value))
+        };
+        match field {
+            "height" => self.height = number(value)?,
+            "hash" => self.hash = value.to_string(),
+            "previous-hash" => self.previous_hash = Some(value.to_string()).filter(|h| !h.is_empty()),
+            "timestamp" => self.timestamp = Some(number(value)? as f64),
+            "executor" => self.executor = value.to_string(),
+            "contract-id" => self.contract_id = value.to_string(),
+            "contract-account" => self.contract_account = Some(value.to_string()).filter(|a| !a.is_empty()),
+            "gas-limit" => self.gas_limit = number(value)?,
+            _ => return Err(format!("Unknown block context field '{}'", field)),
+        }
+        Ok(())
+    }
+
+    // Whether tana/tx may debit `user_id`: the caller, or the contract's own account
+    pub fn may_debit(&self, user_id: &str) -> bool {
+        (!self.executor.is_empty() && user_id == self.executor) || self.contract_account.as_deref() == Some(user_id)
+    }
+
+    // Pin the timestamp for the run (wall clock when none was given)
+    pub fn resolve_timestamp(&mut self) -> f64 {
+        *self.timestamp.get_or_insert_with(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as f64
+        })
+    }
+
+    // One-line description for the startup log
+    pub fn summary(&self) -> String {
+        format!(
+            "height {}, executor {}, gas limit {}, timestamp {}",
+            self.height,
+            if self.executor.is_empty() { "(none)" } else { &self.executor },
+            self.gas_limit,
+            match self.timestamp {
+                Some(ms) => format!("{}", ms),
+                None => "wall clock".to_string(),
+            }
+        )
+    }
+}
+
+// Whether `contract_id` is a system contract (TANA_SYSTEM_CONTRACTS), read once per process
+pub fn is_system_contract(contract_id: &str) -> bool {
+    static SYSTEM: OnceLock<Vec<String>> = OnceLock::new();
+    SYSTEM
+        .get_or_init(|| {
+            env::var("TANA_SYSTEM_CONTRACTS")
+                .map(|ids| {
+                    ids.split(',')
+                        .map(|id| id.trim().to_string())
+                        .filter(|id| !id.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .iter()
+        .any(|id| id == contract_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_every_field() {
+        let mut context = BlockContext::default();
+        for (field, value) in [
+            ("height", "42"),
+            ("hash", "0xabc"),
+            ("previous-hash", "0x123"),
+            ("timestamp", "1735689600000"),
+            ("executor", "user_alice"),
+            ("contract-id", "counter"),
+            ("contract-account", "acct_counter"),
+            ("gas-limit", "500"),
+        ] {
+            context.set(field, value).unwrap();
+        }
+
+        assert_eq!(context.height, 42);
+        assert_eq!(context.hash, "0xabc");
+        assert_eq!(context.previous_hash.as_deref(), Some("0x123"));
+        assert_eq!(context.timestamp, Some(1735689600000.0));
+        assert_eq!(context.executor, "user_alice");
+        assert_eq!(context.contract_id, "counter");
+        assert_eq!(context.contract_account.as_deref(), Some("acct_counter"));
+        assert_eq!(context.gas_limit, 500);
+    }
+
+    #[test]
+    fn set_covers_fields() {
+        let mut context = BlockContext::default();
+        for field in FIELDS {
+            let value = if matches!(*field, "height" | "timestamp" | "gas-limit") { "1" } else { "x" };
+            assert!(context.set(field, value).is_ok(), "{}", field);
+        }
+    }
+
+    #[test]
+    fn set_empty_clears_optional_fields() {
+        let mut context = BlockContext::default();
+        context.set("previous-hash", "0x123").unwrap();
+        context.set("contract-account", "acct").unwrap();
+        context.set("previous-hash", "").unwrap();
+        context.set("contract-account", "").unwrap();
+        assert_eq!(context.previous_hash, None);
+        assert_eq!(context.contract_account, None);
+    }
+
+    #[test]
+    fn set_rejects_bad_values() {
+        let mut context = BlockContext::default();
+        assert!(context.set("height", "-1").is_err());
+        assert!(context.set("gas-limit", "lots").is_err());
+        assert!(context.set("timestamp", "1.5").is_err());
+        assert!(context.set("block", "1").is_err());
+        assert_eq!(context.height, 0);
+        assert_eq!(context.gas_limit, DEFAULT_GAS_LIMIT);
+    }
+
+    #[test]
+    fn may_debit_executor_and_contract_account() {
+        let mut context = BlockContext::default();
+        context.set("executor", "alice").unwrap();
+        context.set("contract-account", "vault").unwrap();
+        assert!(context.may_debit("alice"));
+        assert!(context.may_debit("vault"));
+        assert!(!context.may_debit("bob"));
+        assert!(!context.may_debit(""));
+    }
+
+    #[test]
+    fn may_debit_without_executor() {
+        let context = BlockContext::default();
+        assert!(!context.may_debit(""));
+        assert!(!context.may_debit("alice"));
+    }
+}
@@ -0,0 +1,241 @@
+// Method dispatch: which contract module and export handle a request
+//
+// A contract directory holds one module per method (get.ts, post.ts, put.ts, ...)
+// and/or an index.ts that exports several handlers. Inside the module the export
+// named after the method (Get, Post, Put, Patch, Delete, Head, Options) handles
+// the request, falling back to a catch-all Handler. HEAD falls back to Get.
+//
+// A method goes to the first of <method>, get (HEAD only) and index that exports
+// a handler for it. Which names a module exports is read from its export
+// declarations; a module with `export * from` may export anything, so it gets
+// every method and the run itself checks the export.
+//
+// Methods nothing handles get a 405 with an Allow header listing the methods
+// the contract's modules export handlers for.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use axum::http::Method;
+
+// HTTP method -> exported handler name
+pub const METHOD_EXPORTS: &[(&str, &str)] = &[
+    ("GET", "Get"),
+    ("POST", "Post"),
+    ("PUT", "Put"),
+    ("PATCH", "Patch"),
+    ("DELETE", "Delete"),
+    ("HEAD", "Head"),
+    ("OPTIONS", "Options"),
+];
+
+// Catch-all export for any method without its own handler
+pub const CATCH_ALL_EXPORT: &str = "Handler";
+
+// Module shared by every method
+const INDEX_MODULE: &str = "index";
+
+pub enum ContractModule {
+    // `allow`: the methods the contract handles, for a 405 from the run itself
+    Found { path: PathBuf, is_precompiled: bool, allow: Vec<&'static str> },
+    // No module handles this method; Allow lists the methods that are handled
+    MethodNotAllowed { allow: String },
+    NotFound,
+}
+
+// Module for a request: <method>.js / <method>.ts, else get (HEAD only), else
+// index.js / index.ts, skipping modules without a handler for the method
+// Pre-compiled .js is preferred over .ts source
+pub fn resolve(contract_dir: &Path, method: &Method) -> ContractModule {
+    let modules: Vec<Module> = candidates(method.as_str())
+        .iter()
+        .filter_map(|name| find_module(contract_dir, name))
+        .collect();
+
+    let allow = allowed(contract_dir);
+    match modules.into_iter().find(|module| module.handles(method.as_str())) {
+        Some(module) => ContractModule::Found { path: module.path, is_precompiled: module.is_precompiled, allow },
+        // Some module exists, so this method isn't allowed rather than the contract missing
+        None if has_modules(contract_dir) => ContractModule::MethodNotAllowed { allow: allow.join(", ") },
+        None => ContractModule::NotFound,
+    }
+}
+
+struct Module {
+    path: PathBuf,
+    is_precompiled: bool,
+    // None when it re-exports another module wholesale (export * from)
+    exports: Option<HashSet<String>>,
+}
+
+impl Module {
+    // Whether the module exports a handler for `method` (the dispatch in runner_dispatch)
+    fn handles(&self, method: &str) -> bool {
+        self.exports_handler(method) || self.exports(CATCH_ALL_EXPORT)
+    }
+
+    // Whether it exports the method's own handler (Get for HEAD too)
+    fn exports_handler(&self, method: &str) -> bool {
+        let export = METHOD_EXPORTS.iter().find(|(name, _)| *name == method).map(|(_, export)| *export);
+        export.is_some_and(|export| self.exports(export)) || (method == "HEAD" && self.exports("Get"))
+    }
+
+    fn exports(&self, name: &str) -> bool {
+        self.exports.as_ref().is_none_or(|exports| exports.contains(name))
+    }
+}
+
+// Modules that may handle `method`, in order; only known methods have a module
+// of their own, anything else goes to index's Handler
+fn candidates(method: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = METHOD_EXPORTS
+        .iter()
+        .find(|(name, _)| *name == method)
+        .map(|(name, _)| name.to_ascii_lowercase())
+        .into_iter()
+        .collect();
+    // HEAD is served by get.ts when there is no head.ts
+    if method == "HEAD" {
+        candidates.push("get".to_string());
+    }
+    candidates.push(INDEX_MODULE.to_string());
+    candidates
+}
+
+fn find_module(contract_dir: &Path, name: &str) -> Option<Module> {
+    let js_path = contract_dir.join(format!("{}.js", name));
+    let ts_path = contract_dir.join(format!("{}.ts", name));
+
+    let (path, is_precompiled) = if js_path.exists() {
+        (js_path, true)
+    } else if ts_path.exists() {
+        (ts_path, false)
+    } else {
+        return None;
+    };
+    // An unreadable module fails when the run reads it; until then assume it handles anything
+    let exports = fs::read_to_string(&path).ok().and_then(|source| exported_names(&source));
+    Some(Module { path, is_precompiled, exports })
+}
+
+fn has_modules(contract_dir: &Path) -> bool {
+    METHOD_EXPORTS
+        .iter()
+        .map(|(method, _)| method.to_ascii_lowercase())
+        .chain([INDEX_MODULE.to_string()])
+        .any(|name| {
+            contract_dir.join(format!("{}.js", name)).exists() || contract_dir.join(format!("{}.ts", name)).exists()
+        })
+}
+
+// Methods with a handler of their own in the module they resolve to, or any
+// module before it (GET implies HEAD)
+fn allowed(contract_dir: &Path) -> Vec<&'static str> {
+    let modules: Vec<(String, Option<Module>)> = METHOD_EXPORTS
+        .iter()
+        .map(|(method, _)| method.to_ascii_lowercase())
+        .chain([INDEX_MODULE.to_string()])
+        .map(|name| {
+            let module = find_module(contract_dir, &name);
+            (name, module)
+        })
+        .collect();
+    let module = |name: &str| modules.iter().find(|(n, _)| n == name).and_then(|(_, module)| module.as_ref());
+
+    METHOD_EXPORTS
+        .iter()
+        .map(|(method, _)| *method)
+        .filter(|method| {
+            candidates(method)
+                .iter()
+                .filter_map(|name| module(name))
+                .any(|module| module.exports_handler(method))
+        })
+        .collect()
+}
+
+// Names a module exports, from its export declarations (export function Get,
+// export const Post, export { handle as Put }, ...); None with `export * from`,
+// whose names aren't listed in the module
+fn exported_names(source: &str) -> Option<HashSet<String>> {
+    static DECLARATION: OnceLock<regex::Regex> = OnceLock::new();
+    static LIST: OnceLock<regex::Regex> = OnceLock::new();
+    static STAR: OnceLock<regex::Regex> = OnceLock::new();
+    let declaration = DECLARATION.get_or_init(|| {
+        regex::Regex::new(
+            r"\bexport\s+(?:declare\s+)?(?:async\s+)?(?:function\s*\*?|const|let|var|(?:abstract\s+)?class)\s*([A-Za-z_$][\w$]*)",
+        )
+        .unwrap()
+    });
+    let list = LIST.get_or_init(|| regex::Regex::new(r"\bexport\s+(?:type\s+)?\{([^}]*)\}").unwrap());
+    let star = STAR.get_or_init(|| regex::Regex::new(r"\bexport\s*\*\s*(as\s+[A-Za-z_$][\w$]*\s*)?from\b").unwrap());
+
+    let mut names = HashSet::new();
+    for export in star.captures_iter(source) {
+        match export.get(1) {
+            Some(alias) => names.insert(alias.as_str()[2..].trim().to_string()),
+            None => return None,
+        };
+    }
+    for export in declaration.captures_iter(source) {
+        names.insert(export[1].to_string());
+    }
+    for export in list.captures_iter(source) {
+        for item in export[1].split(',') {
+            // "handle as Get" exports Get; "type Foo" is a type only
+            let item = item.trim();
+            let name = item.rsplit_once(" as ").map_or(item, |(_, alias)| alias).trim();
+            if !name.is_empty() && !item.starts_with("type ") {
+                names.insert(name.to_string());
+            }
+        }
+    }
+    Some(names)
+}
+
+// {status: 405} response with an Allow header
+pub fn method_not_allowed(method: &str, allow: &str) -> serde_json::Value {
+    serde_json::json!({
+        "status": 405,
+        "headers": { "Allow": allow },
+        "body": { "error": format!("Method {} not allowed", method), "allow": allow },
+    })
+}
+
+// JS that picks the handler for `req.method` from `contract` and calls it, or
+// sets a 405 listing `allow` (see resolve) and the methods the module exports
+pub fn runner_dispatch(body_json: &str, allow: &[&str]) -> String {
+    let exports: serde_json::Map<String, serde_json::Value> = METHOD_EXPORTS
+        .iter()
+        .map(|(method, export)| (method.to_string(), serde_json::Value::from(*export)))
+        .collect();
+
+    format!(
+        r#"
+          const exports = {exports};
+          let handler = contract[exports[req.method]];
+          if (typeof handler !== 'function' && req.method === 'HEAD') handler = contract.Get;
+          if (typeof handler !== 'function') handler = contract.{catch_all};
+
+          if (typeof handler === 'function') {{
+            globalThis.__contractResult = await handler(req, {body});
+          }} else {{
+            const allow = Object.keys(exports).filter((method) =>
+              {allow}.includes(method) ||
+              typeof contract[exports[method]] === 'function' ||
+              (method === 'HEAD' && typeof contract.Get === 'function'));
+            globalThis.__contractResult = {{
+              status: 405,
+              headers: {{ Allow: allow.join(', ') }},
+              body: {{ error: `Method ${{req.method}} not allowed`, allow: allow.join(', ') }},
+            }};
+          }}
+        "#,
+        exports = serde_json::Value::Object(exports),
+        catch_all = CATCH_ALL_EXPORT,
+        body = body_json,
+        allow = serde_json::json!(allow),
+    )
+}
@@ -0,0 +1,394 @@
+// Egress policy for contract fetch()
+//
+// Which URLs contracts may reach. Loaded once from TANA_EGRESS_POLICY (a .toml
+// or .json file, see egress.example.toml), plus TANA_EGRESS_ALLOW /
+// TANA_EGRESS_DENY (comma-separated host patterns added to the default rules)
+// and TANA_EGRESS_PRIVATE_HOSTS (added to private_hosts). Without a policy file
+// only tana.dev and its subdomains are allowed; local services such as
+// localhost are opt-in (TANA_EGRESS_ALLOW=localhost plus
+// TANA_EGRESS_PRIVATE_HOSTS=localhost).
+//
+// Host patterns: "api.tana.dev" matches that host only, "*.tana.dev" matches
+// its subdomains (not tana.dev itself) and "*" matches any host. Deny rules win
+// over allow rules.
+//
+// Hosts are checked again after DNS resolution: private, loopback and link-local
+// addresses are refused (SSRF) unless the host is listed in private_hosts.
+// IPv4-mapped and NAT64 addresses are checked by the IPv4 address they carry.
+// Every blocked attempt is logged as an [EGRESS] audit line.
+//
+// Compiled into build.rs along with ops.rs, so this file must only depend on
+// crates listed in both [dependencies] and [build-dependencies].
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::OnceLock;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use serde::Deserialize;
+
+const DEFAULT_SCHEMES: &[&str] = &["http", "https"];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    // Empty: http and https (per contract: the default rules' schemes)
+    pub schemes: Vec<String>,
+    // Empty: any port (per contract: the default rules' ports)
+    pub ports: Vec<u16>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EgressPolicy {
+    // Rules for every contract
+    pub default: Rules,
+    // Per contract id: allow/deny add to the default rules, schemes/ports replace them
+    pub contracts: HashMap<String, Rules>,
+    pub block_private_ips: bool,
+    // Hosts that may resolve to private addresses (local services)
+    pub private_hosts: Vec<String>,
+}
+
+// Missing fields in a policy file are empty; private IPs stay blocked
+impl Default for EgressPolicy {
+    fn default() -> Self {
+        EgressPolicy {
+            default: Rules::default(),
+            contracts: HashMap::new(),
+            block_private_ips: true,
+            private_hosts: Vec::new(),
+        }
+    }
+}
+
+// Why a request was refused (also surfaces through the DNS resolver)
+#[derive(Debug)]
+pub struct Blocked(pub String);
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+impl EgressPolicy {
+    // Policy used when TANA_EGRESS_POLICY is unset: Tana hosts only, private IPs blocked
+    fn fallback() -> EgressPolicy {
+        EgressPolicy {
+            default: Rules {
+                allow: vec!["tana.dev".to_string(), "*.tana.dev".to_string()],
+                ..Rules::default()
+            },
+            ..EgressPolicy::default()
+        }
+    }
+
+    fn load() -> Result<EgressPolicy, String> {
+        let mut policy = match env::var("TANA_EGRESS_POLICY") {
+            Ok(path) => {
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read egress policy {}: {}", path, e))?;
+                if path.ends_with(".json") {
+                    serde_json::from_str(&text).map_err(|e| format!("Invalid egress policy {}: {}", path, e))?
+                } else {
+                    toml::from_str(&text).map_err(|e| format!("Invalid egress policy {}: {}", path, e))?
+                }
+            },
+            Err(_) => EgressPolicy::fallback(),
+        };
+
+        let env_hosts = |name: &str| -> Vec<String> {
+            env::var(name)
+                .map(|v| v.split(',').map(|h| h.trim().to_ascii_lowercase()).filter(|h| !h.is_empty()).collect())
+                .unwrap_or_default()
+        };
+        policy.default.allow.extend(env_hosts("TANA_EGRESS_ALLOW"));
+        policy.default.deny.extend(env_hosts("TANA_EGRESS_DENY"));
+        policy.private_hosts.extend(env_hosts("TANA_EGRESS_PRIVATE_HOSTS"));
+
+        Ok(policy)
+    }
+
+    // One-line description for the startup log
+    pub fn summary(&self) -> String {
+        format!(
+            "{} allow / {} deny rules, {} per-contract policies, private IPs {}",
+            self.default.allow.len(),
+            self.default.deny.len(),
+            self.contracts.len(),
+            if self.block_private_ips { "blocked" } else { "allowed" }
+        )
+    }
+
+    // Can `contract_id` fetch `url`? (DNS results are checked by PolicyResolver)
+    pub fn check(&self, contract_id: &str, url: &Url) -> Result<(), Blocked> {
+        let contract = self.contracts.get(contract_id);
+
+        let schemes = [contract.map(|c| &c.schemes), Some(&self.default.schemes)]
+            .into_iter()
+            .flatten()
+            .find(|schemes| !schemes.is_empty());
+        let scheme_allowed = match schemes {
+            Some(schemes) => schemes.iter().any(|s| s.eq_ignore_ascii_case(url.scheme())),
+            None => DEFAULT_SCHEMES.contains(&url.scheme()),
+        };
+        if !scheme_allowed {
+            return Err(Blocked(format!("scheme \"{}\" not allowed", url.scheme())));
+        }
+
+        // IPv6 literals come bracketed ("[::1]")
+        let host = match url.host_str() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase(),
+            None => return Err(Blocked("URL has no host".to_string())),
+        };
+
+        let port = url.port_or_known_default().unwrap_or(0);
+        let ports = [contract.map(|c| &c.ports), Some(&self.default.ports)]
+            .into_iter()
+            .flatten()
+            .find(|ports| !ports.is_empty());
+        if let Some(ports) = ports {
+            if !ports.contains(&port) {
+                return Err(Blocked(format!("port {} not allowed", port)));
+            }
+        }
+
+        let rules = || std::iter::once(&self.default).chain(contract);
+        if let Some(rule) = rules().flat_map(|r| &r.deny).find(|rule| host_matches(rule, &host)) {
+            return Err(Blocked(format!("host \"{}\" matches deny rule \"{}\"", host, rule)));
+        }
+        if !rules().flat_map(|r| &r.allow).any(|rule| host_matches(rule, &host)) {
+            return Err(Blocked(format!("host \"{}\" not in allowlist", host)));
+        }
+
+        // IP literals never reach the resolver
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            self.check_address(&host, ip)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_address(&self, host: &str, ip: IpAddr) -> Result<(), Blocked> {
+        let private_host_allowed = self.private_hosts.iter().any(|rule| host_matches(rule, host));
+        if self.block_private_ips && is_private(ip) && !private_host_allowed {
+            return Err(Blocked(format!("host \"{}\" resolves to private address {}", host, ip)));
+        }
+        Ok(())
+    }
+}
+
+// Policy read once per process; call at startup so a bad policy file fails fast
+pub fn policy() -> &'static EgressPolicy {
+    static POLICY: OnceLock<EgressPolicy> = OnceLock::new();
+    POLICY.get_or_init(|| EgressPolicy::load().expect("Failed to load egress policy"))
+}
+
+// Audit line for a refused request
+pub fn log_blocked(contract_id: &str, url: &str, blocked: &Blocked) {
+    eprintln!("[EGRESS] blocked contract={} url={} reason={}", contract_id, url, blocked);
+}
+
+fn host_matches(rule: &str, host: &str) -> bool {
+    let rule = rule.trim().to_ascii_lowercase();
+    if rule == "*" {
+        return true;
+    }
+    match rule.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{}", domain)),
+        None => rule == host,
+    }
+}
+
+// Loopback, private, link-local, shared (CGNAT), unspecified and other non-public ranges
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_private_v4(mapped),
+            None => match nat64_embedded(ip) {
+                Some(embedded) => embedded.into_iter().any(is_private_v4),
+                None => is_private_v6(ip),
+            },
+        },
+    }
+}
+
+// IPv4 addresses a NAT64 address may carry (RFC 6052), None for other addresses
+// 64:ff9b::/96 has it in the last 32 bits. Inside 64:ff9b:1::/48 the operator
+// picks the prefix length, so every RFC 6052 reading (/48, /56, /64, /96) that
+// fits the address counts: the shorter ones need octet 8 (u) and the suffix
+// after the IPv4 address to be zero
+fn nat64_embedded(ip: Ipv6Addr) -> Option<Vec<Ipv4Addr>> {
+    let segments = ip.segments();
+    let o = ip.octets();
+    let last = Ipv4Addr::new(o[12], o[13], o[14], o[15]);
+
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return Some(vec![last]);
+    }
+    if segments[..3] == [0x64, 0xff9b, 1] {
+        let zero_from = |start: usize| o[start..].iter().all(|b| *b == 0);
+        let mut embedded = vec![last];
+        if o[8] == 0 {
+            if zero_from(11) {
+                embedded.push(Ipv4Addr::new(o[6], o[7], o[9], o[10]));
+            }
+            if zero_from(12) {
+                embedded.push(Ipv4Addr::new(o[7], o[9], o[10], o[11]));
+            }
+            if zero_from(13) {
+                embedded.push(Ipv4Addr::new(o[9], o[10], o[11], o[12]));
+            }
+        }
+        return Some(embedded);
+    }
+    None
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // fc00::/7 unique local
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10 link-local
+        || (first & 0xffc0) == 0xfe80
+}
+
+// DNS resolver for the fetch client: refuses hosts that resolve to private addresses
+pub struct PolicyResolver;
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_ascii_lowercase();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+
+            // Any private address blocks the host, so a mixed answer can't be used to rebind
+            for addr in &addrs {
+                policy().check_address(&host, addr.ip())?;
+            }
+
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn private(ip: &str) -> bool {
+        is_private(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn exact_hosts() {
+        assert!(host_matches("api.tana.dev", "api.tana.dev"));
+        assert!(host_matches(" API.tana.dev ", "api.tana.dev"));
+        assert!(!host_matches("api.tana.dev", "tana.dev"));
+        assert!(!host_matches("api.tana.dev", "x.api.tana.dev"));
+    }
+
+    #[test]
+    fn wildcard_hosts() {
+        assert!(host_matches("*.tana.dev", "api.tana.dev"));
+        assert!(host_matches("*.tana.dev", "a.b.tana.dev"));
+        assert!(!host_matches("*.tana.dev", "tana.dev"));
+        assert!(!host_matches("*.tana.dev", "eviltana.dev"));
+        assert!(host_matches("*", "example.com"));
+    }
+
+    #[test]
+    fn private_ipv4() {
+        for ip in ["10.0.0.1", "172.16.0.1", "192.168.1.1", "127.0.0.1", "169.254.169.254", "0.0.0.0", "0.1.2.3"] {
+            assert!(private(ip), "{}", ip);
+        }
+        // Shared address space (CGNAT), broadcast, documentation
+        for ip in ["100.64.0.1", "100.127.255.255", "255.255.255.255", "192.0.2.1"] {
+            assert!(private(ip), "{}", ip);
+        }
+        for ip in ["8.8.8.8", "1.1.1.1", "100.128.0.1", "172.32.0.1"] {
+            assert!(!private(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn private_ipv6() {
+        for ip in ["::1", "::", "fc00::1", "fd12:3456::1", "fe80::1", "febf::1"] {
+            assert!(private(ip), "{}", ip);
+        }
+        for ip in ["2606:4700:4700::1111", "2001:4860:4860::8888", "fec0::1"] {
+            assert!(!private(ip), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn ipv4_mapped() {
+        assert!(private("::ffff:127.0.0.1"));
+        assert!(private("::ffff:10.1.2.3"));
+        assert!(!private("::ffff:8.8.8.8"));
+    }
+
+    #[test]
+    fn nat64_well_known_prefix() {
+        assert!(private("64:ff9b::127.0.0.1"));
+        assert!(private("64:ff9b::a9fe:a9fe"));
+        assert!(!private("64:ff9b::8.8.8.8"));
+    }
+
+    #[test]
+    fn nat64_local_use_prefix() {
+        // /96
+        assert!(private("64:ff9b:1::10.0.0.1"));
+        assert!(!private("64:ff9b:1::8.8.8.8"));
+        // /48: 192.168.1.1 in octets 6, 7, 9, 10
+        assert!(private("64:ff9b:1:c0a8:1:100::"));
+        // /64: 10.0.0.1 in octets 9 to 12
+        assert!(private("64:ff9b:1:abcd:a:0:100::"));
+    }
+
+    #[test]
+    fn fallback_allows_tana_hosts_only() {
+        let policy = EgressPolicy::fallback();
+        let check = |url: &str| policy.check("counter", &Url::parse(url).unwrap());
+        assert!(check("https://api.tana.dev/v1").is_ok());
+        assert!(check("https://tana.dev").is_ok());
+        for url in ["https://pokeapi.co/api/v2", "http://localhost:8080", "http://127.0.0.1:6379", "http://[::1]/"] {
+            assert!(check(url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn private_hosts_opt_in() {
+        let mut policy = EgressPolicy::fallback();
+        policy.default.allow.push("127.0.0.1".to_string());
+        let url = Url::parse("http://127.0.0.1:8080").unwrap();
+        assert!(policy.check("counter", &url).is_err());
+        policy.private_hosts.push("127.0.0.1".to_string());
+        assert!(policy.check("counter", &url).is_ok());
+    }
+}
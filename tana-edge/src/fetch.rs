@@ -0,0 +1,292 @@
+// Outbound HTTP for tana/utils fetch()
+//
+// fetch() takes a RequestInit (method, headers, body, timeout) and resolves to
+// the real status, status text, headers and body bytes. Every URL, each redirect
+// hop included, must pass the egress policy (see egress.rs). Responses are capped at
+// TANA_FETCH_MAX_BYTES (default 5 MB), and requests time out after
+// TANA_FETCH_TIMEOUT_MS (default 10s), or sooner if the contract asks.
+//
+// Compiled into build.rs along with ops.rs, so this file must only depend on
+// crates listed in both [dependencies] and [build-dependencies].
+
+use std::env;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use std::sync::Arc;
+
+use deno_core::ToJsBuffer;
+use deno_error::JsErrorBox;
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, LOCATION};
+use reqwest::{Method, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::egress;
+
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_MAX_BYTES: usize = 5 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 5;
+
+// Request headers managed by the HTTP client, not the contract
+const FORBIDDEN_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "host",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+#[derive(Clone, Copy, Debug)]
+struct FetchConfig {
+    timeout: Duration,
+    max_bytes: usize,
+}
+
+// Read once per process
+fn config() -> FetchConfig {
+    static CONFIG: OnceLock<FetchConfig> = OnceLock::new();
+    *CONFIG.get_or_init(|| {
+        let env_number = |name: &str| env::var(name).ok().and_then(|v| v.parse::<u64>().ok()).filter(|n| *n > 0);
+        FetchConfig {
+            timeout: Duration::from_millis(env_number("TANA_FETCH_TIMEOUT_MS").unwrap_or(DEFAULT_TIMEOUT_MS)),
+            max_bytes: env_number("TANA_FETCH_MAX_BYTES").map(|n| n as usize).unwrap_or(DEFAULT_MAX_BYTES),
+        }
+    })
+}
+
+// RequestInit as normalized by the JS fetch() shim: headers as [name, value]
+// pairs, text bodies here and binary bodies in the op's buffer argument
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FetchInit {
+    pub method: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+    // Milliseconds, capped at TANA_FETCH_TIMEOUT_MS
+    pub timeout: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchResponse {
+    pub status: u16,
+    pub status_text: String,
+    // Final URL after redirects
+    pub url: String,
+    pub redirected: bool,
+    pub headers: Vec<(String, String)>,
+    pub body: ToJsBuffer,
+    // Body size in bytes (for gas)
+    #[serde(skip)]
+    pub size: usize,
+}
+
+// A fetched response as plain data (cloneable, so tana-edge can cache it)
+#[derive(Clone, Debug)]
+pub struct Fetched {
+    pub status: u16,
+    pub status_text: String,
+    pub url: String,
+    pub redirected: bool,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl From<Fetched> for FetchResponse {
+    fn from(fetched: Fetched) -> FetchResponse {
+        FetchResponse {
+            status: fetched.status,
+            status_text: fetched.status_text,
+            url: fetched.url,
+            redirected: fetched.redirected,
+            headers: fetched.headers,
+            size: fetched.body.len(),
+            body: ToJsBuffer::from(fetched.body),
+        }
+    }
+}
+
+// Shared client; redirects are followed by fetch() so every hop is checked, and
+// no proxy so DNS answers always go through the egress resolver
+fn client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .no_proxy()
+            .dns_resolver(Arc::new(egress::PolicyResolver))
+            .build()
+            .expect("Failed to build fetch client")
+    })
+}
+
+pub async fn fetch(
+    contract_id: &str,
+    url: &str,
+    init: FetchInit,
+    body_bytes: Vec<u8>,
+) -> Result<Fetched, JsErrorBox> {
+    let config = config();
+
+    // Parse URL
+    let mut url = Url::parse(url)
+        .map_err(|e| JsErrorBox::new("TypeError", format!("Invalid URL: {}", e)))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(JsErrorBox::new(
+            "TypeError",
+            format!("fetch only supports http and https, got \"{}\"", url.scheme()),
+        ));
+    }
+
+    let method = init.method.as_deref().unwrap_or("GET").to_ascii_uppercase();
+    let mut method = Method::from_bytes(method.as_bytes())
+        .map_err(|_| JsErrorBox::new("TypeError", format!("Invalid method: {}", method)))?;
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &init.headers {
+        let header_name = reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| JsErrorBox::new("TypeError", format!("Invalid header name: {}", name)))?;
+        if FORBIDDEN_HEADERS.contains(&header_name.as_str()) {
+            continue;
+        }
+        let header_value = reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| JsErrorBox::new("TypeError", format!("Invalid value for header {}", name)))?;
+        headers.append(header_name, header_value);
+    }
+
+    let timeout = init
+        .timeout
+        .map(Duration::from_millis)
+        .map_or(config.timeout, |timeout| timeout.min(config.timeout));
+    // One deadline for the whole fetch, redirects included
+    let deadline = Instant::now() + timeout;
+
+    let mut body = match init.body {
+        Some(text) => Some(text.into_bytes()),
+        None if !body_bytes.is_empty() => Some(body_bytes),
+        None => None,
+    };
+
+    let mut redirects = 0;
+    let mut response = loop {
+        if let Err(blocked) = egress::policy().check(contract_id, &url) {
+            egress::log_blocked(contract_id, url.as_str(), &blocked);
+            return Err(JsErrorBox::new("Error", format!("fetch blocked: {}", blocked)));
+        }
+
+        let mut request = client()
+            .request(method.clone(), url.clone())
+            .headers(headers.clone())
+            .timeout(deadline.saturating_duration_since(Instant::now()));
+        if let Some(body) = &body {
+            request = request.body(body.clone());
+        }
+
+        // Perform fetch
+        let response = request
+            .send()
+            .await
+            .map_err(|e| send_error(contract_id, &url, timeout, e))?;
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|v| v.to_str().ok());
+        let Some(location) = location.filter(|_| response.status().is_redirection()) else {
+            break response;
+        };
+        if redirects >= MAX_REDIRECTS {
+            return Err(JsErrorBox::new(
+                "Error",
+                format!("fetch failed: too many redirects (max {})", MAX_REDIRECTS),
+            ));
+        }
+        let next = url
+            .join(location)
+            .map_err(|e| JsErrorBox::new("Error", format!("fetch failed: invalid redirect location: {}", e)))?;
+
+        // Same rules as browsers: 303 (and 301/302 after a POST) continue as a GET without the body
+        let status = response.status();
+        if (status == StatusCode::SEE_OTHER && method != Method::HEAD)
+            || (matches!(status, StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND) && method == Method::POST)
+        {
+            method = Method::GET;
+            body = None;
+            headers.remove(CONTENT_TYPE);
+        }
+        // Credentials only go to the origin they were meant for
+        if next.origin() != url.origin() {
+            headers.remove(AUTHORIZATION);
+            headers.remove(COOKIE);
+        }
+
+        url = next;
+        redirects += 1;
+    };
+
+    let too_large = |size: usize| {
+        JsErrorBox::new(
+            "Error",
+            format!("fetch response too large: {} bytes (limit {})", size, config.max_bytes),
+        )
+    };
+    if let Some(length) = response.content_length() {
+        if length as usize > config.max_bytes {
+            return Err(too_large(length as usize));
+        }
+    }
+
+    let status = response.status();
+    let response_headers = response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    // Read in chunks so an unannounced oversized body is cut off early
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| JsErrorBox::new("Error", format!("failed to read response body: {}", e)))?
+    {
+        if body.len() + chunk.len() > config.max_bytes {
+            return Err(too_large(body.len() + chunk.len()));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(Fetched {
+        status: status.as_u16(),
+        status_text: status.canonical_reason().unwrap_or("").to_string(),
+        redirected: redirects > 0,
+        url: url.to_string(),
+        headers: response_headers,
+        body,
+    })
+}
+
+// Requests refused by the egress resolver (private addresses) come back as
+// connect errors with egress::Blocked in their source chain
+fn send_error(contract_id: &str, url: &Url, timeout: Duration, e: reqwest::Error) -> JsErrorBox {
+    let mut source = std::error::Error::source(&e);
+    while let Some(err) = source {
+        if let Some(blocked) = err.downcast_ref::<egress::Blocked>() {
+            egress::log_blocked(contract_id, url.as_str(), blocked);
+            return JsErrorBox::new("Error", format!("fetch blocked: {}", blocked));
+        }
+        source = err.source();
+    }
+
+    if e.is_timeout() {
+        JsErrorBox::new("Error", format!("fetch timed out after {}ms", timeout.as_millis()))
+    } else {
+        JsErrorBox::new("Error", format!("fetch failed: {}", e))
+    }
+}
@@ -0,0 +1,513 @@
+// In-process HTTP cache for contract fetch() (edge only)
+//
+// GET/HEAD requests without a body are cached by method, URL, request headers
+// and timeout, following the upstream Cache-Control: no-store and private
+// responses are never stored, s-maxage/max-age (minus Age) is how long an entry
+// stays fresh, and stale entries with an ETag or Last-Modified are revalidated
+// with a conditional request (a 304 refreshes the entry). Requests the contract
+// marks no-cache/no-store, or sends with conditional or Range headers, skip it.
+//
+// Identical requests from one contract that are in flight together share a
+// single upstream fetch. Cached bodies are capped at TANA_FETCH_CACHE_BYTES
+// (default 16 MB, 0 disables the cache), least recently used entries go first.
+// Hit/miss counts are served at GET /_tana/fetch-cache to callers with the
+// gateway token. Responses that set cookies are never stored.
+//
+// Compiled into build.rs along with ops.rs, so this file must only depend on
+// crates listed in both [dependencies] and [build-dependencies].
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use deno_error::{JsErrorBox, JsErrorClass};
+use reqwest::Url;
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+use crate::egress;
+use crate::fetch::{self, FetchInit, FetchResponse, Fetched};
+
+const DEFAULT_CACHE_BYTES: usize = 16 * 1024 * 1024;
+
+// Bodies bigger than 1/8 of the cache aren't stored
+const MAX_ENTRY_SHARE: usize = 8;
+
+// Statuses cacheable by default (RFC 9110 15.1) that fetch() can return
+const CACHEABLE_STATUS: &[u16] = &[200, 203, 204, 300, 301, 404, 405, 410, 414, 501];
+
+// Request headers that mean the contract is doing its own caching
+const BYPASS_HEADERS: &[&str] = &[
+    "if-match",
+    "if-modified-since",
+    "if-none-match",
+    "if-range",
+    "if-unmodified-since",
+    "range",
+];
+
+// Outcome of one upstream fetch, shared by every request waiting on it
+type Shared = Result<Arc<Fetched>, (Cow<'static, str>, Cow<'static, str>)>;
+
+struct Entry {
+    response: Arc<Fetched>,
+    stored_at: Instant,
+    fresh_for: Duration,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, Entry>,
+    // Sum of cached body sizes
+    bytes: usize,
+    // Access counter for LRU eviction
+    tick: u64,
+}
+
+enum Lookup {
+    Fresh(Arc<Fetched>),
+    // Expired but has validators: revalidate instead of refetching
+    Stale(Arc<Fetched>),
+    Missing,
+}
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    revalidated: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    bypassed: AtomicU64,
+    stored: AtomicU64,
+    evicted: AtomicU64,
+    bytes_saved: AtomicU64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheStats {
+    // Served from a fresh entry
+    pub hits: u64,
+    // Served from a stale entry after a 304
+    pub revalidated: u64,
+    // Fetched upstream
+    pub misses: u64,
+    // Waited on an identical request in flight
+    pub coalesced: u64,
+    // Not cacheable (method, body, request headers) or cache disabled
+    pub bypassed: u64,
+    pub stored: u64,
+    pub evicted: u64,
+    // Upstream body bytes not downloaded thanks to hits and 304s
+    pub bytes_saved: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+// Max cached body bytes, read once per process
+fn capacity() -> usize {
+    static CAPACITY: OnceLock<usize> = OnceLock::new();
+    *CAPACITY.get_or_init(|| {
+        env::var("TANA_FETCH_CACHE_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_CACHE_BYTES)
+    })
+}
+
+fn cache() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+// Upstream fetches in progress, keyed by contract id + cache key
+fn in_flight() -> &'static Mutex<HashMap<String, Arc<OnceCell<Shared>>>> {
+    static IN_FLIGHT: OnceLock<Mutex<HashMap<String, Arc<OnceCell<Shared>>>>> = OnceLock::new();
+    IN_FLIGHT.get_or_init(Mutex::default)
+}
+
+fn counters() -> &'static Counters {
+    static COUNTERS: OnceLock<Counters> = OnceLock::new();
+    COUNTERS.get_or_init(Counters::default)
+}
+
+pub fn stats() -> CacheStats {
+    let counters = counters();
+    let cache = cache().lock().unwrap();
+    CacheStats {
+        hits: counters.hits.load(Ordering::Relaxed),
+        revalidated: counters.revalidated.load(Ordering::Relaxed),
+        misses: counters.misses.load(Ordering::Relaxed),
+        coalesced: counters.coalesced.load(Ordering::Relaxed),
+        bypassed: counters.bypassed.load(Ordering::Relaxed),
+        stored: counters.stored.load(Ordering::Relaxed),
+        evicted: counters.evicted.load(Ordering::Relaxed),
+        bytes_saved: counters.bytes_saved.load(Ordering::Relaxed),
+        entries: cache.entries.len(),
+        bytes: cache.bytes,
+        capacity: capacity(),
+    }
+}
+
+// fetch::fetch through the cache
+pub async fn fetch(
+    contract_id: &str,
+    url: &str,
+    init: FetchInit,
+    body: Vec<u8>,
+) -> Result<FetchResponse, JsErrorBox> {
+    let Some(key) = cache_key(url, &init, &body) else {
+        counters().bypassed.fetch_add(1, Ordering::Relaxed);
+        return Ok(fetch::fetch(contract_id, url, init, body).await?.into());
+    };
+
+    // Entries are shared between contracts, so each one's egress policy still applies
+    check_policy(contract_id, url)?;
+
+    let stale = match lookup(&key) {
+        Lookup::Fresh(response) => {
+            check_policy(contract_id, &response.url)?;
+            counters().hits.fetch_add(1, Ordering::Relaxed);
+            counters().bytes_saved.fetch_add(response.body.len() as u64, Ordering::Relaxed);
+            return Ok(Fetched::clone(&response).into());
+        },
+        Lookup::Stale(response) => Some(response),
+        Lookup::Missing => None,
+    };
+
+    // Join an identical fetch in flight, or start one. Per contract, so a
+    // redirect blocked by one contract's policy isn't another's error
+    let flight_key = format!("{}\n{}", contract_id, key);
+    let cell = in_flight().lock().unwrap().entry(flight_key.clone()).or_default().clone();
+    let mut leader = false;
+    let shared = cell
+        .get_or_init(|| {
+            leader = true;
+            fetch_and_store(contract_id, &key, url, init, body, stale)
+        })
+        .await
+        .clone();
+    if !leader {
+        counters().coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Done: later requests go to the cache (or upstream again)
+    let mut in_flight = in_flight().lock().unwrap();
+    if in_flight.get(&flight_key).is_some_and(|current| Arc::ptr_eq(current, &cell)) {
+        in_flight.remove(&flight_key);
+    }
+    drop(in_flight);
+
+    match shared {
+        Ok(response) => Ok(Fetched::clone(&response).into()),
+        Err((class, message)) => Err(JsErrorBox::new(class, message)),
+    }
+}
+
+async fn fetch_and_store(
+    contract_id: &str,
+    key: &str,
+    url: &str,
+    mut init: FetchInit,
+    body: Vec<u8>,
+    stale: Option<Arc<Fetched>>,
+) -> Shared {
+    let authorized = init
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("authorization"));
+
+    if let Some(stale) = &stale {
+        if let Some(etag) = header(stale, "etag") {
+            init.headers.push(("If-None-Match".to_string(), etag.to_string()));
+        }
+        if let Some(last_modified) = header(stale, "last-modified") {
+            init.headers.push(("If-Modified-Since".to_string(), last_modified.to_string()));
+        }
+    }
+
+    let fetched = fetch::fetch(contract_id, url, init, body)
+        .await
+        .map_err(|e| (e.get_class(), e.get_message()))?;
+
+    // Not modified: the stale entry with the 304's headers (new Cache-Control, ETag...)
+    if let (304, Some(stale)) = (fetched.status, &stale) {
+        let mut refreshed = Fetched::clone(stale);
+        for (name, _) in &fetched.headers {
+            refreshed.headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        }
+        refreshed.headers.extend(fetched.headers);
+
+        let refreshed = Arc::new(refreshed);
+        if let Some(fresh_for) = freshness(&refreshed, authorized) {
+            store(key, &refreshed, fresh_for);
+        }
+        counters().revalidated.fetch_add(1, Ordering::Relaxed);
+        counters().bytes_saved.fetch_add(refreshed.body.len() as u64, Ordering::Relaxed);
+        return Ok(refreshed);
+    }
+
+    counters().misses.fetch_add(1, Ordering::Relaxed);
+    let fetched = Arc::new(fetched);
+    if let Some(fresh_for) = freshness(&fetched, authorized) {
+        store(key, &fetched, fresh_for);
+    }
+    Ok(fetched)
+}
+
+// None when the request can't be served from the cache
+fn cache_key(url: &str, init: &FetchInit, body: &[u8]) -> Option<String> {
+    if capacity() == 0 || init.body.is_some() || !body.is_empty() {
+        return None;
+    }
+
+    let method = init.method.as_deref().unwrap_or("GET").to_ascii_uppercase();
+    if method != "GET" && method != "HEAD" {
+        return None;
+    }
+    let url = Url::parse(url).ok()?;
+
+    let mut headers: Vec<(String, &str)> = init
+        .headers
+        .iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.as_str()))
+        .collect();
+    for (name, value) in &headers {
+        if BYPASS_HEADERS.contains(&name.as_str()) {
+            return None;
+        }
+        if (name == "cache-control" || name == "pragma") && (value.contains("no-cache") || value.contains("no-store")) {
+            return None;
+        }
+    }
+    headers.sort();
+
+    // Every request header is part of the key, which covers any Vary
+    let mut key = format!("{} {} {:?}", method, url, init.timeout);
+    for (name, value) in headers {
+        key.push('\n');
+        key.push_str(&name);
+        key.push(':');
+        key.push_str(value);
+    }
+    Some(key)
+}
+
+fn lookup(key: &str) -> Lookup {
+    let mut cache = cache().lock().unwrap();
+    cache.tick += 1;
+    let tick = cache.tick;
+
+    let Some(entry) = cache.entries.get_mut(key) else {
+        return Lookup::Missing;
+    };
+    entry.last_used = tick;
+
+    if entry.stored_at.elapsed() < entry.fresh_for {
+        return Lookup::Fresh(entry.response.clone());
+    }
+    if header(&entry.response, "etag").is_some() || header(&entry.response, "last-modified").is_some() {
+        return Lookup::Stale(entry.response.clone());
+    }
+
+    if let Some(expired) = cache.entries.remove(key) {
+        cache.bytes -= expired.response.body.len();
+    }
+    Lookup::Missing
+}
+
+fn store(key: &str, response: &Arc<Fetched>, fresh_for: Duration) {
+    let size = response.body.len();
+    let capacity = capacity();
+    if size > capacity / MAX_ENTRY_SHARE {
+        return;
+    }
+
+    let mut cache = cache().lock().unwrap();
+    if let Some(old) = cache.entries.remove(key) {
+        cache.bytes -= old.response.body.len();
+    }
+
+    // Evict least recently used entries until the body fits
+    while cache.bytes + size > capacity {
+        let Some(oldest) = cache
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        if let Some(evicted) = cache.entries.remove(&oldest) {
+            cache.bytes -= evicted.response.body.len();
+            counters().evicted.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    cache.tick += 1;
+    let entry = Entry {
+        response: response.clone(),
+        stored_at: Instant::now(),
+        fresh_for,
+        last_used: cache.tick,
+    };
+    cache.entries.insert(key.to_string(), entry);
+    cache.bytes += size;
+    counters().stored.fetch_add(1, Ordering::Relaxed);
+}
+
+// How long a response may be served without revalidating; None if it can't be stored
+fn freshness(response: &Fetched, authorized: bool) -> Option<Duration> {
+    if !CACHEABLE_STATUS.contains(&response.status) {
+        return None;
+    }
+    if headers(response, "vary").any(|vary| vary.trim() == "*") {
+        return None;
+    }
+    // Cookies are per client; a shared entry would hand one caller's session to another
+    if header(response, "set-cookie").is_some() {
+        return None;
+    }
+
+    let directives: Vec<(String, Option<String>)> = headers(response, "cache-control")
+        .flat_map(|value| value.split(','))
+        .map(|directive| match directive.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_ascii_lowercase(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (directive.trim().to_ascii_lowercase(), None),
+        })
+        .collect();
+    let has = |name: &str| directives.iter().any(|(directive, _)| directive == name);
+    let seconds = |name: &str| {
+        directives
+            .iter()
+            .find(|(directive, _)| directive == name)
+            .and_then(|(_, value)| value.as_deref()?.parse::<u64>().ok())
+    };
+
+    // The edge is a shared cache (RFC 9111 3.5 for requests with credentials)
+    if has("no-store") || has("private") {
+        return None;
+    }
+    if authorized && !(has("public") || has("s-maxage") || has("must-revalidate")) {
+        return None;
+    }
+
+    let validators = header(response, "etag").is_some() || header(response, "last-modified").is_some();
+    let max_age = if has("no-cache") {
+        Some(0)
+    } else {
+        seconds("s-maxage").or_else(|| seconds("max-age"))
+    };
+
+    match max_age {
+        Some(max_age) => {
+            let age = header(response, "age")
+                .and_then(|age| age.trim().parse::<u64>().ok())
+                .unwrap_or(0);
+            let fresh_for = max_age.saturating_sub(age);
+            // Already stale and nothing to revalidate with
+            if fresh_for == 0 && !validators {
+                None
+            } else {
+                Some(Duration::from_secs(fresh_for))
+            }
+        },
+        // No explicit lifetime: keep it, but revalidate on every use
+        None if validators => Some(Duration::ZERO),
+        None => None,
+    }
+}
+
+fn headers<'a>(response: &'a Fetched, name: &'a str) -> impl Iterator<Item = &'a str> {
+    response
+        .headers
+        .iter()
+        .filter(move |(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn header<'a>(response: &'a Fetched, name: &'a str) -> Option<&'a str> {
+    headers(response, name).next()
+}
+
+fn check_policy(contract_id: &str, url: &str) -> Result<(), JsErrorBox> {
+    // Invalid URLs are reported by fetch::fetch
+    let Ok(parsed) = Url::parse(url) else {
+        return Ok(());
+    };
+    egress::policy().check(contract_id, &parsed).map_err(|blocked| {
+        egress::log_blocked(contract_id, url, &blocked);
+        JsErrorBox::new("Error", format!("fetch blocked: {}", blocked))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status: u16, headers: &[(&str, &str)]) -> Fetched {
+        Fetched {
+            status,
+            status_text: String::new(),
+            url: "https://api.tana.dev/".to_string(),
+            redirected: false,
+            headers: headers.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: Vec::new(),
+        }
+    }
+
+    fn fresh_for(headers: &[(&str, &str)]) -> Option<u64> {
+        freshness(&response(200, headers), false).map(|fresh| fresh.as_secs())
+    }
+
+    #[test]
+    fn max_age() {
+        assert_eq!(fresh_for(&[("Cache-Control", "public, max-age=60")]), Some(60));
+        assert_eq!(fresh_for(&[("cache-control", "MAX-AGE=\"30\"")]), Some(30));
+        // s-maxage is the shared-cache lifetime
+        assert_eq!(fresh_for(&[("Cache-Control", "max-age=60, s-maxage=10")]), Some(10));
+    }
+
+    #[test]
+    fn age_is_subtracted() {
+        assert_eq!(fresh_for(&[("Cache-Control", "max-age=60"), ("Age", "45")]), Some(15));
+        // Expired: kept only when it can be revalidated
+        assert_eq!(fresh_for(&[("Cache-Control", "max-age=60"), ("Age", "90")]), None);
+        assert_eq!(fresh_for(&[("Cache-Control", "max-age=60"), ("Age", "90"), ("ETag", "\"v1\"")]), Some(0));
+    }
+
+    #[test]
+    fn not_stored() {
+        assert_eq!(fresh_for(&[("Cache-Control", "no-store, max-age=60")]), None);
+        assert_eq!(fresh_for(&[("Cache-Control", "private, max-age=60")]), None);
+        assert_eq!(fresh_for(&[("Cache-Control", "max-age=60"), ("Vary", "*")]), None);
+        assert_eq!(fresh_for(&[("Cache-Control", "public, max-age=60"), ("Set-Cookie", "session=abc")]), None);
+        assert_eq!(fresh_for(&[]), None);
+        assert_eq!(freshness(&response(500, &[("Cache-Control", "max-age=60")]), false), None);
+    }
+
+    #[test]
+    fn revalidate_every_use() {
+        assert_eq!(fresh_for(&[("Cache-Control", "no-cache"), ("ETag", "\"v1\"")]), Some(0));
+        assert_eq!(fresh_for(&[("Cache-Control", "no-cache")]), None);
+        assert_eq!(fresh_for(&[("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT")]), Some(0));
+    }
+
+    #[test]
+    fn authorized_requests_need_explicit_permission() {
+        let private = response(200, &[("Cache-Control", "max-age=60")]);
+        assert_eq!(freshness(&private, true), None);
+        let public = response(200, &[("Cache-Control", "public, max-age=60")]);
+        assert_eq!(freshness(&public, true), Some(Duration::from_secs(60)));
+        let shared = response(200, &[("Cache-Control", "s-maxage=5")]);
+        assert_eq!(freshness(&shared, true), Some(Duration::from_secs(5)));
+    }
+}
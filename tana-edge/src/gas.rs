@@ -0,0 +1,293 @@
+// Gas schedule and per-run gas meter
+//
+// Every op charges a fixed cost plus a per-byte (or per-item) cost for the data
+// it touches, and compute is charged per statement: the loader injects a
+// __tanaGas(n) call into every block of contract code (see loader.rs). Both
+// only count what the contract does, so the same contract and input use the
+// same gas on any machine; wall time only matters to the watchdog (limits.rs).
+// Charging past the gas limit terminates the isolate, so a contract can't catch
+// "Out of gas" and keep running.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use deno_core::{v8, JsRuntime, OpState};
+
+// ========== Schedule ==========
+
+// Charged once when a run starts
+pub const BASE_EXECUTION: u64 = 1_000;
+
+// Compute: per statement of a block entered (loop iterations, function calls, ...)
+pub const STATEMENT: u64 = 1;
+
+// Logging (op_print_stderr)
+pub const LOG: u64 = 10;
+pub const LOG_BYTE: u64 = 1;
+
+// tana/data
+pub const DATA_READ: u64 = 200;
+pub const DATA_READ_BYTE: u64 = 1;
+pub const DATA_WRITE: u64 = 500;
+pub const DATA_WRITE_BYTE: u64 = 5;
+pub const DATA_DELETE: u64 = 200;
+pub const DATA_KEYS: u64 = 300;
+pub const DATA_KEYS_ITEM: u64 = 10;
+pub const DATA_CLEAR: u64 = 1_000;
+pub const DATA_COMMIT: u64 = 1_000;
+pub const DATA_COMMIT_BYTE: u64 = 10;
+
+// tana/block
+pub const BLOCK_CONTEXT: u64 = 20;
+pub const BLOCK_QUERY: u64 = 1_000;
+pub const BLOCK_QUERY_ITEM: u64 = 200;
+
+// tana/tx
+pub const TX_STAGE: u64 = 500;
+pub const TX_EXECUTE: u64 = 2_000;
+pub const TX_EXECUTE_CHANGE: u64 = 100;
+
+// tana/net request body (Request.text/json/formData/arrayBuffer)
+pub const REQUEST_BODY: u64 = 100;
+pub const REQUEST_BODY_BYTE: u64 = 1;
+
+// tana/net streamed response body
+pub const STREAM_CHUNK: u64 = 50;
+pub const STREAM_BYTE: u64 = 1;
+
+// tana/utils fetch
+pub const FETCH: u64 = 5_000;
+pub const FETCH_BYTE: u64 = 1;
+
+// Gas for entering blocks with `statements` statements in total
+pub fn compute_gas(statements: u64) -> u64 {
+    statements.saturating_mul(STATEMENT)
+}
+
+// ========== Meter ==========
+
+// Gas used by one run (put into OpState by install_meter)
+pub struct GasMeter {
+    limit: u64,
+    used: AtomicU64,
+    exhausted: AtomicBool,
+    isolate: v8::IsolateHandle,
+}
+
+impl GasMeter {
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::SeqCst)
+    }
+
+    // Charge an op or compute; false (and the isolate terminated) once the limit is passed
+    pub fn charge(&self, amount: u64) -> bool {
+        let used = self.used.fetch_add(amount, Ordering::SeqCst).saturating_add(amount);
+        if used <= self.limit {
+            return true;
+        }
+        if !self.exhausted.swap(true, Ordering::SeqCst) {
+            self.isolate.terminate_execution();
+        }
+        false
+    }
+}
+
+// Start metering a run: replaces any meter left from a previous run on this isolate
+pub fn install_meter(runtime: &mut JsRuntime, limit: u64) -> Arc<GasMeter> {
+    let meter = Arc::new(GasMeter {
+        limit,
+        used: AtomicU64::new(BASE_EXECUTION),
+        exhausted: AtomicBool::new(false),
+        isolate: runtime.v8_isolate().thread_safe_handle(),
+    });
+    runtime.op_state().borrow_mut().put(meter.clone());
+    meter
+}
+
+// TypeScript transformer (see loader.rs) that meters every block: `{ a; b; }` becomes
+// `{ __tanaGas(2); a; b; }` (at least 1, so empty loops are charged too), loop
+// bodies without braces are wrapped in a metered block, and arrow functions
+// with an expression body charge 1 per call. Contract code naming __tanaGas
+// is rejected so it can't be shadowed.
+pub const METER_TRANSFORMER: &str = r#"
+(context) => {
+  const f = ts.factory;
+  const tick = (count) =>
+    f.createCallExpression(f.createIdentifier("__tanaGas"), undefined, [f.createNumericLiteral(Math.max(count, 1))]);
+
+  // After any "use strict"-style directives, which must stay first
+  const metered = (statements) => {
+    let prologue = 0;
+    while (
+      prologue < statements.length &&
+      ts.isExpressionStatement(statements[prologue]) &&
+      ts.isStringLiteral(statements[prologue].expression)
+    ) {
+      prologue++;
+    }
+    return [
+      ...statements.slice(0, prologue),
+      f.createExpressionStatement(tick(statements.length - prologue)),
+      ...statements.slice(prologue),
+    ];
+  };
+
+  const visit = (node) => {
+    if (ts.isIdentifier(node) && node.text === "__tanaGas") {
+      throw new Error("__tanaGas is reserved");
+    }
+
+    if (ts.isIterationStatement(node, false) && !ts.isBlock(node.statement)) {
+      const body = node.statement;
+      return ts.visitEachChild(
+        node,
+        (child) => (child === body ? f.createBlock(metered([ts.visitNode(child, visit)]), true) : visit(child)),
+        context,
+      );
+    }
+
+    node = ts.visitEachChild(node, visit, context);
+    if (ts.isBlock(node)) {
+      return f.updateBlock(node, metered(node.statements));
+    }
+    if (ts.isCaseClause(node)) {
+      return f.updateCaseClause(node, node.expression, metered(node.statements));
+    }
+    if (ts.isDefaultClause(node)) {
+      return f.updateDefaultClause(node, metered(node.statements));
+    }
+    if (ts.isArrowFunction(node) && !ts.isBlock(node.body)) {
+      const body = f.createParenthesizedExpression(f.createComma(tick(1), node.body));
+      return f.updateArrowFunction(
+        node,
+        node.modifiers,
+        node.typeParameters,
+        node.parameters,
+        node.type,
+        node.equalsGreaterThanToken,
+        body,
+      );
+    }
+    return node;
+  };
+
+  return (file) => {
+    const visited = ts.visitNode(file, visit);
+    return f.updateSourceFile(visited, metered(visited.statements));
+  };
+}
+"#;
+
+// Defines the __tanaGas(n) hook the loader injects into contract code. It can't
+// be replaced, and WebAssembly is removed since compiled wasm couldn't be metered.
+// Run once per isolate, after the bootstrap.
+pub const HOOK_SCRIPT: &str = r#"
+(() => {
+  const charge = globalThis.__tanaCore.ops.op_gas_statements;
+  Object.defineProperty(globalThis, "__tanaGas", {
+    value: (statements) => charge(statements),
+    writable: false,
+    configurable: false,
+    enumerable: false,
+  });
+  delete globalThis.WebAssembly;
+})();
+"#;
+
+pub fn install_hook(runtime: &mut JsRuntime) -> Result<(), String> {
+    runtime
+        .execute_script("tana-gas.js", deno_core::ModuleCodeString::from(HOOK_SCRIPT.to_string()))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to install gas hook: {}", e))
+}
+
+pub fn meter(state: &OpState) -> Option<Arc<GasMeter>> {
+    state.try_borrow::<Arc<GasMeter>>().cloned()
+}
+
+// Charge the current run from inside an op (no-op before a meter is installed)
+pub fn charge(state: &OpState, amount: u64) -> Result<(), deno_error::JsErrorBox> {
+    match meter(state) {
+        Some(meter) if !meter.charge(amount) => Err(out_of_gas(&meter)),
+        _ => Ok(()),
+    }
+}
+
+fn out_of_gas(meter: &GasMeter) -> deno_error::JsErrorBox {
+    deno_error::JsErrorBox::new("Error", format!("Out of gas (limit {})", meter.limit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deno_core::RuntimeOptions;
+
+    #[test]
+    fn compute_gas_per_statement() {
+        assert_eq!(compute_gas(0), 0);
+        assert_eq!(compute_gas(7), 7 * STATEMENT);
+        assert_eq!(compute_gas(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn charges_add_up_from_the_base_cost() {
+        let mut runtime = JsRuntime::new(RuntimeOptions::default());
+        let meter = install_meter(&mut runtime, 10_000);
+        assert_eq!(meter.used(), BASE_EXECUTION);
+
+        let state = runtime.op_state();
+        charge(&state.borrow(), DATA_READ).unwrap();
+        charge(&state.borrow(), compute_gas(50)).unwrap();
+        assert_eq!(meter.used(), BASE_EXECUTION + DATA_READ + compute_gas(50));
+        assert!(!meter.is_exhausted());
+    }
+
+    #[test]
+    fn limit_is_inclusive() {
+        let mut runtime = JsRuntime::new(RuntimeOptions::default());
+        let meter = install_meter(&mut runtime, BASE_EXECUTION + 100);
+        assert!(meter.charge(100));
+        assert!(!meter.is_exhausted());
+        assert!(!meter.charge(1));
+        assert!(meter.is_exhausted());
+    }
+
+    #[test]
+    fn out_of_gas_stays_out() {
+        let mut runtime = JsRuntime::new(RuntimeOptions::default());
+        let meter = install_meter(&mut runtime, BASE_EXECUTION + 10);
+
+        let state = runtime.op_state();
+        let error = charge(&state.borrow(), 11).unwrap_err();
+        assert!(error.to_string().contains(&format!("Out of gas (limit {})", BASE_EXECUTION + 10)));
+        assert!(meter.is_exhausted());
+        assert!(charge(&state.borrow(), 0).is_err());
+        assert_eq!(meter.limit(), BASE_EXECUTION + 10);
+    }
+
+    #[test]
+    fn new_meter_replaces_the_previous_run() {
+        let mut runtime = JsRuntime::new(RuntimeOptions::default());
+        let first = install_meter(&mut runtime, BASE_EXECUTION);
+        assert!(!first.charge(1));
+
+        let second = install_meter(&mut runtime, BASE_EXECUTION + 5);
+        charge(&runtime.op_state().borrow(), 5).unwrap();
+        assert_eq!(second.used(), BASE_EXECUTION + 5);
+        assert_eq!(first.used(), BASE_EXECUTION + 1);
+    }
+
+    #[test]
+    fn no_meter_no_charge() {
+        let runtime = JsRuntime::new(RuntimeOptions::default());
+        assert!(charge(&runtime.op_state().borrow(), u64::MAX).is_ok());
+    }
+}
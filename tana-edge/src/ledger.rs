@@ -0,0 +1,490 @@
+// Ledger API client for tana/block queries
+//
+// Where the ledger lives and how to reach it, read once per process: the
+// TANA_LEDGER_CONFIG file (.toml or .json, every field optional) first, then
+// these env vars on top:
+//
+//   TANA_LEDGER_URL                 base URL (default http://localhost:8080)
+//   TANA_LEDGER_TOKEN               sent as "Authorization: Bearer <token>"
+//   TANA_LEDGER_TIMEOUT_MS          per attempt, default 5000
+//   TANA_LEDGER_CONNECT_TIMEOUT_MS  default 2000
+//   TANA_LEDGER_RETRIES             extra attempts after a failure, default 2
+//   TANA_LEDGER_RETRY_BACKOFF_MS    first retry delay, doubled each time, default 200
+//
+// Lookups use the ledger's per-id endpoints (/users/:id, /transactions/:id,
+// /blocks/:height, /balances?ownerId=...), never the full tables. Connection errors, timeouts,
+// 429 and 5xx responses are retried; 404 means "not found" and other statuses
+// fail right away. tx.execute() settles through POST /transactions/batch with
+// an idempotency key; when the outcome is unclear (no response, 5xx) the batch
+// is read back by that key and only submitted again if the ledger never applied
+// it. The ledger is operator config, so these requests don't go through the
+// contract egress policy.
+//
+// Compiled into build.rs along with ops.rs, so this file must only depend on
+// crates listed in both [dependencies] and [build-dependencies].
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LedgerConfig {
+    pub url: String,
+    pub token: Option<String>,
+    pub timeout_ms: u64,
+    pub connect_timeout_ms: u64,
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for LedgerConfig {
+    fn default() -> Self {
+        LedgerConfig {
+            url: "http://localhost:8080".to_string(),
+            token: None,
+            timeout_ms: 5_000,
+            connect_timeout_ms: 2_000,
+            retries: 2,
+            retry_backoff_ms: 200,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LedgerError {
+    // Couldn't get a response (after retries)
+    Request(String),
+    // Non-2xx response
+    Status(u16),
+    // Response body isn't the expected JSON
+    Parse(String),
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Request(e) => write!(f, "{}", e),
+            LedgerError::Status(status) => write!(f, "ledger returned HTTP {}", status),
+            LedgerError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl LedgerConfig {
+    fn load() -> Result<LedgerConfig, String> {
+        let mut config = match env::var("TANA_LEDGER_CONFIG") {
+            Ok(path) => {
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read ledger config {}: {}", path, e))?;
+                if path.ends_with(".json") {
+                    serde_json::from_str(&text).map_err(|e| format!("Invalid ledger config {}: {}", path, e))?
+                } else {
+                    toml::from_str(&text).map_err(|e| format!("Invalid ledger config {}: {}", path, e))?
+                }
+            },
+            Err(_) => LedgerConfig::default(),
+        };
+
+        if let Ok(url) = env::var("TANA_LEDGER_URL") {
+            config.url = url;
+        }
+        if let Ok(token) = env::var("TANA_LEDGER_TOKEN") {
+            config.token = Some(token).filter(|t| !t.is_empty());
+        }
+        let env_number = |name: &str| -> Result<Option<u64>, String> {
+            match env::var(name) {
+                Ok(v) => v.parse().map(Some).map_err(|_| format!("{} must be a number, got '{}'", name, v)),
+                Err(_) => Ok(None),
+            }
+        };
+        if let Some(ms) = env_number("TANA_LEDGER_TIMEOUT_MS")? {
+            config.timeout_ms = ms;
+        }
+        if let Some(ms) = env_number("TANA_LEDGER_CONNECT_TIMEOUT_MS")? {
+            config.connect_timeout_ms = ms;
+        }
+        if let Some(retries) = env_number("TANA_LEDGER_RETRIES")? {
+            config.retries = retries as u32;
+        }
+        if let Some(ms) = env_number("TANA_LEDGER_RETRY_BACKOFF_MS")? {
+            config.retry_backoff_ms = ms;
+        }
+
+        // Paths are appended, so drop the trailing slash
+        config.url = config.url.trim_end_matches('/').to_string();
+        reqwest::Url::parse(&config.url).map_err(|e| format!("Invalid ledger URL '{}': {}", config.url, e))?;
+
+        Ok(config)
+    }
+
+    // One-line description for the startup log (never includes the token)
+    pub fn summary(&self) -> String {
+        format!(
+            "{} ({}, timeout {}ms, {} retries)",
+            self.url,
+            if self.token.is_some() { "bearer token" } else { "no auth" },
+            self.timeout_ms,
+            self.retries
+        )
+    }
+}
+
+// Config read once per process; call at startup so a bad config fails fast
+pub fn config() -> &'static LedgerConfig {
+    static CONFIG: OnceLock<LedgerConfig> = OnceLock::new();
+    CONFIG.get_or_init(|| LedgerConfig::load().expect("Failed to load ledger config"))
+}
+
+fn http() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        let config = config();
+        reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+            .build()
+            .expect("Failed to build ledger client")
+    })
+}
+
+// Balance of a user in one currency (0 when they hold none)
+// A malformed amount is an error, never a zero balance
+pub async fn balance(user_id: &str, currency_code: &str) -> Result<f64, LedgerError> {
+    let query = [("ownerId", user_id), ("ownerType", "user"), ("currencyCode", currency_code)];
+    match get::<serde_json::Value>(&["balances"], &query).await? {
+        Some(balance) => balance_amount(&balance),
+        None => Ok(0.0),
+    }
+}
+
+// Amount of a /balances response: a balance row ({amount: "12.5", ...}), or
+// {balance: "0"} when the user holds none; amounts are decimal strings
+fn balance_amount(balance: &serde_json::Value) -> Result<f64, LedgerError> {
+    let (field, amount) = match (balance.get("amount"), balance.get("balance")) {
+        (Some(amount), _) => ("amount", amount),
+        (None, Some(amount)) => ("balance", amount),
+        (None, None) => return Err(LedgerError::Parse(format!("balance has no amount: {}", balance))),
+    };
+    amount
+        .as_str()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|amount| amount.is_finite())
+        .ok_or_else(|| LedgerError::Parse(format!("invalid balance {}: {}", field, amount)))
+}
+
+// User by id, then by username
+pub async fn user(id_or_username: &str) -> Result<Option<serde_json::Value>, LedgerError> {
+    if let Some(user) = get(&["users", id_or_username], &[]).await? {
+        return Ok(Some(user));
+    }
+    get(&["users", "username", id_or_username], &[]).await
+}
+
+pub async fn transaction(id: &str) -> Result<Option<serde_json::Value>, LedgerError> {
+    get(&["transactions", id], &[]).await
+}
+
+// Block at `height`, as tana/block's BlockInfo
+pub async fn block(height: u64) -> Result<Option<serde_json::Value>, LedgerError> {
+    let block = get(&["blocks", &height.to_string()], &[]).await?;
+    Ok(block.map(block_info))
+}
+
+// Highest block; None before genesis
+pub async fn latest_block() -> Result<Option<serde_json::Value>, LedgerError> {
+    let block = get(&["blocks", "latest"], &[]).await?;
+    Ok(block.map(block_info))
+}
+
+// Ledger block row -> BlockInfo (see types/tana-block.d.ts): numbers instead of
+// decimal strings, the timestamp in ms since the epoch, internal columns dropped
+fn block_info(block: serde_json::Value) -> serde_json::Value {
+    let number = |value: &serde_json::Value| value.as_f64().or_else(|| value.as_str()?.parse::<f64>().ok());
+    serde_json::json!({
+        "height": number(&block["height"]),
+        "hash": block["hash"],
+        "previousHash": block["previousHash"],
+        "timestamp": timestamp_ms(&block["timestamp"]),
+        "producer": block["producer"],
+        "gasLimit": number(&block["gasLimit"]),
+        "gasUsed": number(&block["gasUsed"]),
+        "stateRoot": block["stateRoot"],
+        "txCount": number(&block["txCount"]),
+        "metadata": block["metadata"],
+    })
+}
+
+// RFC 3339 timestamp as the ledger's JSON dates ("2025-01-31T12:00:00.000Z") -> ms since the epoch
+fn timestamp_ms(value: &serde_json::Value) -> Option<f64> {
+    if let Some(ms) = value.as_f64() {
+        return Some(ms);
+    }
+    let (date, time) = value.as_str()?.split_once(['T', 't', ' '])?;
+
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+
+    // Offset in minutes east of UTC
+    let (clock, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(clock) => (clock, 0),
+        None => {
+            let split = time.rfind(['+', '-'])?;
+            let (clock, offset) = time.split_at(split);
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let minutes = hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?;
+            (clock, if offset.starts_with('-') { -minutes } else { minutes })
+        },
+    };
+
+    let mut clock = clock.splitn(3, ':');
+    let hour = clock.next()?.parse::<i64>().ok()?;
+    let minute = clock.next()?.parse::<i64>().ok()?;
+    let second = clock.next()?.parse::<f64>().ok()?;
+
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 - offset * 60;
+    Some(((seconds as f64 + second) * 1000.0).round())
+}
+
+// Days since 1970-01-01 in the proleptic Gregorian calendar (Howard Hinnant's algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Outcome of settling a run's tx changes (see settle)
+pub enum Settlement {
+    // Applied; the ledger's transaction records, one per change
+    Settled(Vec<serde_json::Value>),
+    // Refused as a whole (invalid change, insufficient balance, ...); nothing applied
+    Rejected { error: String, index: Option<usize> },
+    // Still unclear after the retries: the batch may or may not have been applied
+    Unresolved { batch: String, error: LedgerError },
+}
+
+// Submit a run's staged tx changes as one atomic batch (POST /transactions/batch)
+// On an unclear outcome, read the batch back by its key (GET /transactions/batch/:key)
+// and submit it again only if the ledger doesn't have it; the key makes that safe
+pub async fn settle(
+    contract_id: &str,
+    executor: &str,
+    changes: &[serde_json::Value],
+) -> Result<Settlement, LedgerError> {
+    // The ledger takes amounts as decimal strings
+    let changes: Vec<serde_json::Value> = changes
+        .iter()
+        .map(|change| {
+            let mut change = change.clone();
+            if let Some(amount) = change["amount"].as_f64() {
+                change["amount"] = serde_json::json!(amount.to_string());
+            }
+            change
+        })
+        .collect();
+    let batch = batch_id(contract_id);
+    let body = serde_json::json!({
+        "contractId": contract_id,
+        "executor": Some(executor).filter(|e| !e.is_empty()),
+        "idempotencyKey": batch,
+        "changes": changes,
+    });
+
+    let config = config();
+    let mut attempt = 0;
+    loop {
+        let failure = match submit(&body).await? {
+            Ok(settlement) => return Ok(settlement),
+            Err(failure) => failure,
+        };
+
+        // The ledger may have applied it before failing; the read waits for a
+        // settlement of this batch still in progress
+        eprintln!("[LEDGER] settling batch {} failed ({}), reading it back", batch, failure);
+        match get::<serde_json::Value>(&["transactions", "batch", &batch], &[]).await {
+            Ok(Some(result)) => return Ok(Settlement::Settled(records(&result))),
+            Ok(None) => {},
+            Err(e) => return Ok(Settlement::Unresolved { batch, error: e }),
+        }
+
+        if attempt >= config.retries {
+            return Ok(Settlement::Unresolved { batch, error: failure });
+        }
+        let backoff = config.retry_backoff_ms.saturating_mul(1 << attempt.min(16));
+        eprintln!("[LEDGER] batch {} not applied, submitting again in {}ms", batch, backoff);
+        tokio::time::sleep(Duration::from_millis(backoff)).await;
+        attempt += 1;
+    }
+}
+
+// One POST /transactions/batch; the inner Err is an unclear outcome (no
+// response, 5xx, unreadable body), the outer one a batch that was never sent
+async fn submit(body: &serde_json::Value) -> Result<Result<Settlement, LedgerError>, LedgerError> {
+    let url = endpoint(&["transactions", "batch"])?;
+    let response = match authorized(http().post(url).json(body)).send().await {
+        Ok(response) => response,
+        Err(e) => return Ok(Err(LedgerError::Request(e.to_string()))),
+    };
+
+    let status = response.status();
+    if status.is_server_error() {
+        return Ok(Err(LedgerError::Status(status.as_u16())));
+    }
+    let result: serde_json::Value = match response.json().await {
+        Ok(result) => result,
+        Err(e) => return Ok(Err(LedgerError::Parse(e.to_string()))),
+    };
+
+    if status.is_success() {
+        return Ok(Ok(Settlement::Settled(records(&result))));
+    }
+
+    // Validation errors come back as an object; keep them readable
+    let error = match &result["error"] {
+        serde_json::Value::String(error) => error.clone(),
+        serde_json::Value::Null => format!("ledger returned HTTP {}", status.as_u16()),
+        error => error.to_string(),
+    };
+    Ok(Ok(Settlement::Rejected {
+        error,
+        index: result["index"].as_u64().map(|i| i as usize),
+    }))
+}
+
+// Transaction records of a settled batch ({transactions: [...]})
+fn records(result: &serde_json::Value) -> Vec<serde_json::Value> {
+    result["transactions"].as_array().cloned().unwrap_or_default()
+}
+
+// Idempotency key of one settlement, unique across runs and processes:
+// "<contract id>:<process start, ns since the epoch>-<pid>-<counter>"
+fn batch_id(contract_id: &str) -> String {
+    static PROCESS: OnceLock<String> = OnceLock::new();
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let process = PROCESS.get_or_init(|| {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        format!("{:x}-{:x}", started, std::process::id())
+    });
+    format!("{}:{}-{}", contract_id, process, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+// <ledger url>/<segments>
+fn endpoint(segments: &[&str]) -> Result<reqwest::Url, LedgerError> {
+    let config = config();
+    let mut url = reqwest::Url::parse(&config.url).map_err(|e| LedgerError::Request(e.to_string()))?;
+    url.path_segments_mut()
+        .map_err(|_| LedgerError::Request(format!("Invalid ledger URL '{}'", config.url)))?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
+
+fn authorized(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match &config().token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+// GET <ledger url>/<segments>?<query> and parse the JSON body; None on 404
+async fn get<T: DeserializeOwned>(segments: &[&str], query: &[(&str, &str)]) -> Result<Option<T>, LedgerError> {
+    let config = config();
+
+    // Segments are percent-encoded, so ids can't reach other endpoints; "", "."
+    // and ".." would still change the path, and no record has such an id
+    if segments.iter().any(|segment| matches!(*segment, "" | "." | "..")) {
+        return Ok(None);
+    }
+    let mut url = endpoint(segments)?;
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+
+    let mut attempt = 0;
+    let response = loop {
+        let failure = match authorized(http().get(url.clone())).send().await {
+            Ok(response) if response.status().is_success() => break response,
+            Ok(response) if response.status() == reqwest::StatusCode::NOT_FOUND => return Ok(None),
+            Ok(response) if response.status().is_server_error() || response.status().as_u16() == 429 => {
+                LedgerError::Status(response.status().as_u16())
+            },
+            Ok(response) => return Err(LedgerError::Status(response.status().as_u16())),
+            Err(e) => LedgerError::Request(e.to_string()),
+        };
+
+        if attempt >= config.retries {
+            return Err(failure);
+        }
+        let backoff = config.retry_backoff_ms.saturating_mul(1 << attempt.min(16));
+        eprintln!("[LEDGER] GET {} failed ({}), retrying in {}ms", url, failure, backoff);
+        tokio::time::sleep(Duration::from_millis(backoff)).await;
+        attempt += 1;
+    };
+
+    response
+        .json()
+        .await
+        .map(Some)
+        .map_err(|e| LedgerError::Parse(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn timestamp_utc() {
+        assert_eq!(timestamp_ms(&json!("2025-01-31T12:00:00.000Z")), Some(1738324800000.0));
+        assert_eq!(timestamp_ms(&json!("2025-01-31t12:00:00z")), Some(1738324800000.0));
+        assert_eq!(timestamp_ms(&json!("2024-02-29T00:00:00Z")), Some(1709164800000.0));
+        assert_eq!(timestamp_ms(&json!("1969-12-31T23:59:59Z")), Some(-1000.0));
+    }
+
+    #[test]
+    fn timestamp_offsets() {
+        // 12:00:00.5 at +02:00 is 10:00:00.5 UTC
+        assert_eq!(timestamp_ms(&json!("2025-01-31T12:00:00.5+02:00")), Some(1738317600500.0));
+        assert_eq!(timestamp_ms(&json!("2025-01-31 10:00:00-02:00")), Some(1738324800000.0));
+    }
+
+    #[test]
+    fn timestamp_numbers_and_garbage() {
+        assert_eq!(timestamp_ms(&json!(1738324800000u64)), Some(1738324800000.0));
+        assert_eq!(timestamp_ms(&json!("2025-01-31")), None);
+        assert_eq!(timestamp_ms(&json!("2025-01-31T12:00")), None);
+        assert_eq!(timestamp_ms(&json!("2025-01-31T12:00:00+0200")), None);
+        assert_eq!(timestamp_ms(&json!("not a date")), None);
+        assert_eq!(timestamp_ms(&json!(null)), None);
+    }
+
+    #[test]
+    fn days_from_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+
+    #[test]
+    fn balance_shapes() {
+        assert_eq!(balance_amount(&json!({ "amount": "12.5", "currencyCode": "USD" })).unwrap(), 12.5);
+        assert_eq!(balance_amount(&json!({ "balance": "0", "currencyCode": "USD" })).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn malformed_balances_are_errors() {
+        assert!(matches!(balance_amount(&json!({ "currencyCode": "USD" })), Err(LedgerError::Parse(_))));
+        assert!(matches!(balance_amount(&json!({ "amount": 12.5 })), Err(LedgerError::Parse(_))));
+        assert!(matches!(balance_amount(&json!({ "amount": "twelve" })), Err(LedgerError::Parse(_))));
+        assert!(matches!(balance_amount(&json!({ "amount": "NaN" })), Err(LedgerError::Parse(_))));
+    }
+}
@@ -0,0 +1,361 @@
+// Execution limits for contract runs
+//
+// Every run gets a watchdog thread that terminates the isolate
+// (IsolateHandle::terminate_execution) once the run passes its wall-clock
+// deadline or its busy-time budget. Busy time is wall time spent inside V8
+// (script execution and event loop polls), not thread CPU time: time spent
+// waiting on async ops like fetch only counts against the wall clock, but a
+// preempted thread still counts as busy. When the watchdog is dropped, a
+// termination still pending is cancelled so the isolate can be reused.
+//
+// Once a streamed Response has sent its head (see stream.rs), the wall clock no
+// longer applies; the run is bounded by the time since its last chunk
+// (TANA_STREAM_IDLE_TIMEOUT_MS) and the stream's total time
+// (TANA_STREAM_TIMEOUT_MS) instead, and still by its busy-time budget.
+//
+// Each isolate is also created with a max heap size. When V8 gets close to it,
+// the near-heap-limit callback terminates the isolate instead of letting V8
+// abort the whole process.
+
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use deno_core::{v8, JsRuntime, PollEventLoopOptions};
+
+use crate::gas::{self, GasMeter};
+use crate::stream::StreamProgress;
+
+// Defaults (override with TANA_TIMEOUT_MS / TANA_BUSY_TIMEOUT_MS / TANA_HEAP_MB /
+// TANA_STREAM_IDLE_TIMEOUT_MS / TANA_STREAM_TIMEOUT_MS)
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_BUSY_TIMEOUT_MS: u64 = 2_000;
+const DEFAULT_HEAP_MB: u64 = 128;
+const DEFAULT_STREAM_IDLE_TIMEOUT_MS: u64 = 30_000;
+const DEFAULT_STREAM_TIMEOUT_MS: u64 = 300_000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExecutionLimits {
+    pub wall: Duration,
+    // Wall time inside V8 (see guard)
+    pub busy: Duration,
+    // Max V8 heap size in bytes
+    pub heap: usize,
+    // Streamed Responses: max time between chunks, and in total
+    pub stream_idle: Duration,
+    pub stream_total: Duration,
+}
+
+impl ExecutionLimits {
+    pub fn from_env() -> ExecutionLimits {
+        ExecutionLimits {
+            wall: Duration::from_millis(env_ms("TANA_TIMEOUT_MS", DEFAULT_TIMEOUT_MS)),
+            // TANA_CPU_TIMEOUT_MS is the old name
+            busy: Duration::from_millis(env_ms(
+                "TANA_BUSY_TIMEOUT_MS",
+                env_ms("TANA_CPU_TIMEOUT_MS", DEFAULT_BUSY_TIMEOUT_MS),
+            )),
+            heap: (env_ms("TANA_HEAP_MB", DEFAULT_HEAP_MB) as usize) * 1024 * 1024,
+            stream_idle: Duration::from_millis(env_ms("TANA_STREAM_IDLE_TIMEOUT_MS", DEFAULT_STREAM_IDLE_TIMEOUT_MS)),
+            stream_total: Duration::from_millis(env_ms("TANA_STREAM_TIMEOUT_MS", DEFAULT_STREAM_TIMEOUT_MS)),
+        }
+    }
+
+    // Isolate parameters for RuntimeOptions::create_params
+    pub fn create_params(&self) -> v8::CreateParams {
+        v8::CreateParams::default().heap_limits(0, self.heap)
+    }
+}
+
+// Limits read once per process
+pub fn limits() -> ExecutionLimits {
+    static LIMITS: OnceLock<ExecutionLimits> = OnceLock::new();
+    *LIMITS.get_or_init(ExecutionLimits::from_env)
+}
+
+fn env_ms(name: &str, default: u64) -> u64 {
+    env::var(name)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|ms| *ms > 0)
+        .unwrap_or(default)
+}
+
+// The limit that stopped a run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    WallClock(Duration),
+    Busy(Duration),
+    // A streamed Response sent nothing for this long
+    StreamIdle(Duration),
+    // A streamed Response was open for this long
+    StreamTotal(Duration),
+    // Heap size in bytes
+    Memory(usize),
+    // Gas limit
+    Gas(u64),
+}
+
+impl Limit {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Limit::WallClock(_) => "wall_clock",
+            Limit::Busy(_) => "busy",
+            Limit::StreamIdle(_) => "stream_idle",
+            Limit::StreamTotal(_) => "stream_total",
+            Limit::Memory(_) => "memory",
+            Limit::Gas(_) => "gas",
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Limit::WallClock(_) | Limit::Busy(_) | Limit::StreamIdle(_) | Limit::StreamTotal(_)
+        )
+    }
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::WallClock(limit) => {
+                write!(f, "Execution timed out (wall-clock limit {}ms)", limit.as_millis())
+            },
+            Limit::Busy(limit) => write!(f, "Execution timed out (busy-time limit {}ms)", limit.as_millis()),
+            Limit::StreamIdle(limit) => write!(f, "Stream timed out (idle limit {}ms)", limit.as_millis()),
+            Limit::StreamTotal(limit) => write!(f, "Stream timed out (stream limit {}ms)", limit.as_millis()),
+            Limit::Memory(heap) => write!(f, "Memory limit exceeded (heap limit {}MB)", heap / (1024 * 1024)),
+            Limit::Gas(limit) => write!(f, "Out of gas (limit {})", limit),
+        }
+    }
+}
+
+// Why a contract run failed (timeouts become HTTP 504, other limits HTTP 500)
+#[derive(Debug)]
+pub enum ExecutionError {
+    LimitExceeded(Limit),
+    Failed(String),
+}
+
+impl From<String> for ExecutionError {
+    fn from(e: String) -> Self {
+        ExecutionError::Failed(e)
+    }
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::LimitExceeded(limit) => write!(f, "{}", limit),
+            ExecutionError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+struct WatchState {
+    finished: bool,
+    busy: Duration,
+    // Set while V8 is executing on the run's thread
+    running_since: Option<Instant>,
+    fired: Option<Limit>,
+}
+
+// Raised by the near-heap-limit callback; kept in OpState for the runtime's lifetime
+#[derive(Clone)]
+struct HeapLimit {
+    bytes: usize,
+    exceeded: Arc<AtomicBool>,
+}
+
+// Terminate the isolate when it nears its max heap size (see create_params)
+// Call once per runtime, right after JsRuntime::new
+pub fn install_heap_limit(runtime: &mut JsRuntime, limits: ExecutionLimits) {
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let isolate = runtime.v8_isolate().thread_safe_handle();
+
+    let flag = exceeded.clone();
+    runtime.add_near_heap_limit_callback(move |current_limit, _initial_limit| {
+        flag.store(true, Ordering::SeqCst);
+        isolate.terminate_execution();
+        // Headroom so the terminated script can unwind instead of V8 aborting the process
+        current_limit * 2
+    });
+
+    runtime.op_state().borrow_mut().put(HeapLimit {
+        bytes: limits.heap,
+        exceeded,
+    });
+}
+
+pub struct Watchdog {
+    isolate: v8::IsolateHandle,
+    limits: ExecutionLimits,
+    deadline: Instant,
+    heap: Option<HeapLimit>,
+    gas: Option<Arc<GasMeter>>,
+    stream: Option<StreamProgress>,
+    state: Arc<(Mutex<WatchState>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub fn start(runtime: &mut JsRuntime, limits: ExecutionLimits) -> Watchdog {
+        let isolate = runtime.v8_isolate().thread_safe_handle();
+        let deadline = Instant::now() + limits.wall;
+        let heap = runtime.op_state().borrow().try_borrow::<HeapLimit>().cloned();
+        let gas = gas::meter(&runtime.op_state().borrow());
+        let stream = runtime.op_state().borrow().try_borrow::<StreamProgress>().cloned();
+        let state = Arc::new((
+            Mutex::new(WatchState {
+                finished: false,
+                busy: Duration::ZERO,
+                running_since: None,
+                fired: None,
+            }),
+            Condvar::new(),
+        ));
+
+        let watched = state.clone();
+        let watcher = isolate.clone();
+        let progress = stream.clone();
+        let thread = thread::spawn(move || {
+            let (lock, cvar) = &*watched;
+            let mut state = lock.lock().unwrap();
+
+            while !state.finished {
+                let now = Instant::now();
+                let busy = state.busy + state.running_since.map(|t| now - t).unwrap_or_default();
+                // Stream limits are checked by run_event_loop, between chunks
+                let streaming = progress.as_ref().is_some_and(|p| p.times().is_some());
+
+                let fired = if now >= deadline && !streaming {
+                    Some(Limit::WallClock(limits.wall))
+                } else if busy >= limits.busy {
+                    Some(Limit::Busy(limits.busy))
+                } else {
+                    None
+                };
+                if let Some(limit) = fired {
+                    state.fired = Some(limit);
+                    watcher.terminate_execution();
+                    break;
+                }
+
+                // Sleep until a limit could be hit (guard() wakes us to recompute)
+                let busy_left = state.running_since.map(|_| limits.busy - busy);
+                let wait = match (streaming, busy_left) {
+                    (true, busy_left) => busy_left,
+                    (false, Some(busy_left)) => Some(busy_left.min(deadline - now)),
+                    (false, None) => Some(deadline - now),
+                };
+                state = match wait {
+                    Some(wait) => cvar.wait_timeout(state, wait).unwrap().0,
+                    None => cvar.wait(state).unwrap(),
+                };
+            }
+        });
+
+        Watchdog {
+            isolate,
+            limits,
+            deadline,
+            heap,
+            gas,
+            stream,
+            state,
+            thread: Some(thread),
+        }
+    }
+
+    // Run V8 work that counts against the busy-time budget
+    pub fn guard<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.update(|state| state.running_since = Some(Instant::now()));
+        let result = f();
+        self.update(|state| {
+            if let Some(since) = state.running_since.take() {
+                state.busy += since.elapsed();
+            }
+        });
+        result
+    }
+
+    // Drive the event loop until it is idle, within the run's deadline
+    pub async fn run_event_loop(&self, runtime: &mut JsRuntime) -> Result<(), String> {
+        let event_loop = std::future::poll_fn(|cx| {
+            self.guard(|| runtime.poll_event_loop(cx, PollEventLoopOptions::default()))
+        });
+        let mut event_loop = std::pin::pin!(event_loop);
+
+        // The watchdog can only interrupt running JS; this covers waiting on ops.
+        // A stream's deadline moves with every chunk, so check it again on timeout
+        loop {
+            let (deadline, _) = self.deadline();
+            match tokio::time::timeout_at(deadline.into(), event_loop.as_mut()).await {
+                Ok(result) => return result.map_err(|e| format!("Event loop failed: {}", e)),
+                Err(_) => {
+                    let (deadline, limit) = self.deadline();
+                    if deadline > Instant::now() {
+                        continue;
+                    }
+                    self.update(|state| {
+                        state.fired.get_or_insert(limit);
+                    });
+                    return Err("Event loop timed out".to_string());
+                },
+            }
+        }
+    }
+
+    // When the run must be done by, and the limit that sets it
+    fn deadline(&self) -> (Instant, Limit) {
+        match self.stream.as_ref().and_then(StreamProgress::times) {
+            Some((started, last)) => {
+                let idle = last + self.limits.stream_idle;
+                let total = started + self.limits.stream_total;
+                if idle < total {
+                    (idle, Limit::StreamIdle(self.limits.stream_idle))
+                } else {
+                    (total, Limit::StreamTotal(self.limits.stream_total))
+                }
+            },
+            None => (self.deadline, Limit::WallClock(self.limits.wall)),
+        }
+    }
+
+    // Which limit (if any) stopped the run
+    pub fn exceeded(&self) -> Option<Limit> {
+        if let Some(heap) = &self.heap {
+            if heap.exceeded.load(Ordering::SeqCst) {
+                return Some(Limit::Memory(heap.bytes));
+            }
+        }
+        if let Some(meter) = &self.gas {
+            if meter.is_exhausted() {
+                return Some(Limit::Gas(meter.limit()));
+            }
+        }
+        self.state.0.lock().unwrap().fired
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WatchState)) {
+        let (lock, cvar) = &*self.state;
+        f(&mut lock.lock().unwrap());
+        cvar.notify_one();
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.update(|state| state.finished = true);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // Nothing can fire anymore; don't leave a termination (ours, the gas
+        // meter's or the heap limit's) pending for whatever runs next
+        self.isolate.cancel_terminate_execution();
+    }
+}
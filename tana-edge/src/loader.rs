@@ -0,0 +1,374 @@
+// ES module loader for contracts
+//
+// - `tana/<name>` and `tana:<name>` resolve to synthetic `tana:<name>` modules that
+//   re-export the bootstrapped tanaModules entries (named exports + default)
+// - `./` and `../` imports resolve to .ts/.js files inside the contract directory;
+//   anything that would leave the directory (including via symlinks) is rejected
+// - contract modules are registered right before the run by prepare_contract, which
+//   walks the import graph and compiles every module with the compiler in the isolate:
+//   TypeScript is transpiled, and every block gets a __tanaGas(n) call charging its
+//   statements (see gas.rs), so compute gas doesn't depend on how fast V8 runs
+// - the compiler is captured by __tanaCompile (install_compiler) before any contract
+//   code runs and the global `ts` is hidden, so contracts can't reach or patch it
+// - modules that weren't prepared for the run (e.g. a computed dynamic import) are
+//   refused, since they would run unmetered
+//
+// Each run gets its own specifier (?run=N) so module-level state never carries
+// over between requests served by the same pooled isolate. V8 keeps every
+// loaded module, so the pool recycles an isolate once it has registered
+// TANA_POOL_MAX_MODULES of them (see module_count).
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use deno_core::{
+    JsRuntime,
+    ModuleCodeString,
+    ModuleLoadResponse,
+    ModuleLoader,
+    ModuleSource,
+    ModuleSourceCode,
+    ModuleSpecifier,
+    ModuleType,
+    RequestedModuleType,
+    ResolutionKind,
+};
+use deno_error::JsErrorBox;
+
+use crate::gas;
+
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// File types a contract may import
+const MODULE_EXTENSIONS: &[&str] = &["ts", "js", "mjs"];
+
+#[derive(Default)]
+pub struct TanaModuleLoader {
+    // tana:<name> -> synthetic module source (kept for the isolate's lifetime)
+    tana_modules: RefCell<HashMap<String, String>>,
+    // Contract modules waiting to be loaded (removed once V8 has them)
+    sources: RefCell<HashMap<ModuleSpecifier, String>>,
+    // Directory of the contract being run; relative imports must stay inside it
+    root: RefCell<Option<PathBuf>>,
+    // Contract modules registered so far; V8 keeps every one in the isolate's
+    // module map, so a long-lived isolate grows with each run
+    registered: Cell<usize>,
+}
+
+impl TanaModuleLoader {
+    // Build a synthetic module for every tanaModules entry (call after bootstrap)
+    pub fn register_tana_modules(&self, runtime: &mut JsRuntime) -> Result<(), String> {
+        let listing = eval_string(
+            runtime,
+            "tana-modules.js",
+            "JSON.stringify(Object.fromEntries(Object.keys(tanaModules).map((spec) => [spec, Object.keys(tanaModules[spec])])))",
+        )?;
+        let modules: HashMap<String, Vec<String>> = serde_json::from_str(&listing)
+            .map_err(|e| format!("Failed to list tana modules: {}", e))?;
+
+        let mut tana_modules = self.tana_modules.borrow_mut();
+        for (spec, exports) in modules {
+            let name = match spec.strip_prefix("tana/") {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            let mut source = format!(
+                "const m = globalThis.__tanaImport({});\nexport default m;\n",
+                serde_json::to_string(&spec).unwrap()
+            );
+            for export in exports.iter().filter(|e| is_identifier(e) && e.as_str() != "default") {
+                source.push_str(&format!("export const {0} = m.{0};\n", export));
+            }
+
+            tana_modules.insert(name, source);
+        }
+
+        Ok(())
+    }
+
+    // Register a contract and the local modules it imports for the next run
+    // Returns the entry module's specifier (import it to run the contract)
+    pub fn prepare_contract(
+        &self,
+        runtime: &mut JsRuntime,
+        entry: &Path,
+        source: String,
+    ) -> Result<ModuleSpecifier, String> {
+        let entry = entry
+            .canonicalize()
+            .map_err(|e| format!("Failed to resolve contract path {}: {}", entry.display(), e))?;
+        let root = entry
+            .parent()
+            .ok_or_else(|| format!("Contract has no directory: {}", entry.display()))?
+            .to_path_buf();
+        *self.root.borrow_mut() = Some(root);
+
+        let run = format!("run={}", RUN_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let has_typescript = eval_string(runtime, "tana-has-ts.js", "typeof __tanaCompile === 'function'")? == "true";
+
+        // Metering is injected by the compiler, so pre-compiled JS needs it too
+        if !has_typescript {
+            return Err(format!("Contract needs the TypeScript compiler for gas metering: {}", entry.display()));
+        }
+
+        let mut pending = vec![(entry.clone(), Some(source))];
+        let mut seen = HashSet::new();
+        while let Some((path, source)) = pending.pop() {
+            if !seen.insert(path.clone()) {
+                continue;
+            }
+
+            let source = match source {
+                Some(source) => source,
+                None => fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read module {}: {}", path.display(), e))?,
+            };
+
+            let (code, imports) = compile_module(runtime, &path, &source)?;
+
+            // Unresolvable imports are left for V8 to report when it requests them
+            let dir = path.parent().unwrap_or(&path);
+            for import in imports.iter().filter(|i| is_relative(i)) {
+                if let Ok(dep) = self.resolve_local(dir, import) {
+                    pending.push((dep, None));
+                }
+            }
+
+            self.register(&path, &run, code)?;
+        }
+
+        let mut specifier = ModuleSpecifier::from_file_path(&entry)
+            .map_err(|_| format!("Invalid contract path: {}", entry.display()))?;
+        specifier.set_query(Some(&run));
+        Ok(specifier)
+    }
+
+    fn register(&self, path: &Path, run: &str, code: String) -> Result<ModuleSpecifier, String> {
+        let mut specifier = ModuleSpecifier::from_file_path(path)
+            .map_err(|_| format!("Invalid module path: {}", path.display()))?;
+        specifier.set_query(Some(run));
+
+        self.sources.borrow_mut().insert(specifier.clone(), code);
+        self.registered.set(self.registered.get() + 1);
+        Ok(specifier)
+    }
+
+    // Contract modules this isolate has registered (the pool recycles isolates
+    // past TANA_POOL_MAX_MODULES)
+    pub fn module_count(&self) -> usize {
+        self.registered.get()
+    }
+
+    // Resolve a ./ or ../ import from `dir` to a module file inside the contract directory
+    // Tries the exact path, then .ts/.js, then index.ts/index.js
+    fn resolve_local(&self, dir: &Path, specifier: &str) -> Result<PathBuf, String> {
+        let root = self.root.borrow().clone()
+            .ok_or_else(|| "No contract directory for relative import".to_string())?;
+
+        let base = dir.join(specifier);
+        let mut candidates = vec![base.clone()];
+        for ext in ["ts", "js"] {
+            candidates.push(PathBuf::from(format!("{}.{}", base.display(), ext)));
+        }
+        // TypeScript convention: `./lib.js` may refer to lib.ts
+        if base.extension().is_some_and(|ext| ext == "js") {
+            candidates.push(base.with_extension("ts"));
+        }
+        for index in ["index.ts", "index.js"] {
+            candidates.push(base.join(index));
+        }
+
+        for candidate in candidates {
+            let is_module = candidate
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| MODULE_EXTENSIONS.contains(&ext));
+            if !is_module || !candidate.is_file() {
+                continue;
+            }
+
+            let resolved = candidate
+                .canonicalize()
+                .map_err(|e| format!("Failed to resolve {}: {}", specifier, e))?;
+            if !resolved.starts_with(&root) {
+                return Err(format!("Import outside the contract directory is not allowed: {}", specifier));
+            }
+            return Ok(resolved);
+        }
+
+        Err(format!("Module not found: {}", specifier))
+    }
+}
+
+impl ModuleLoader for TanaModuleLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, JsErrorBox> {
+        if let Some(name) = specifier.strip_prefix("tana/").or_else(|| specifier.strip_prefix("tana:")) {
+            return ModuleSpecifier::parse(&format!("tana:{}", name))
+                .map_err(|e| JsErrorBox::new("TypeError", format!("Invalid tana module {}: {}", specifier, e)));
+        }
+
+        // The entry module, imported by specifier from the runner
+        if let Ok(registered) = ModuleSpecifier::parse(specifier) {
+            if self.sources.borrow().contains_key(&registered) {
+                return Ok(registered);
+            }
+        }
+
+        if !is_relative(specifier) {
+            return Err(JsErrorBox::new(
+                "TypeError",
+                format!(
+                    "Import \"{}\" is not allowed (only tana modules and ./ or ../ imports inside the contract directory)",
+                    specifier
+                ),
+            ));
+        }
+
+        let referrer = ModuleSpecifier::parse(referrer)
+            .ok()
+            .filter(|r| r.scheme() == "file")
+            .ok_or_else(|| JsErrorBox::new("TypeError", format!("Relative import \"{}\" outside a contract module", specifier)))?;
+        let referrer_path = referrer
+            .to_file_path()
+            .map_err(|_| JsErrorBox::new("TypeError", format!("Invalid referrer: {}", referrer)))?;
+        let dir = referrer_path.parent().unwrap_or(&referrer_path);
+
+        let path = self
+            .resolve_local(dir, specifier)
+            .map_err(|e| JsErrorBox::new("TypeError", e))?;
+        let mut resolved = ModuleSpecifier::from_file_path(&path)
+            .map_err(|_| JsErrorBox::new("TypeError", format!("Invalid module path: {}", path.display())))?;
+        // Same run as the importing module
+        resolved.set_query(referrer.query());
+
+        Ok(resolved)
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<&ModuleSpecifier>,
+        _is_dyn_import: bool,
+        _requested_module_type: RequestedModuleType,
+    ) -> ModuleLoadResponse {
+        let code = if module_specifier.scheme() == "tana" {
+            self.tana_modules
+                .borrow()
+                .get(module_specifier.path())
+                .cloned()
+                .ok_or_else(|| JsErrorBox::new("TypeError", format!("unknown tana module: {}", module_specifier)))
+        } else {
+            self.sources
+                .borrow_mut()
+                .remove(module_specifier)
+                .ok_or_else(|| JsErrorBox::new("TypeError", format!("Module not prepared for this run: {}", module_specifier)))
+        };
+
+        ModuleLoadResponse::Sync(code.map(|code| {
+            ModuleSource::new(
+                ModuleType::JavaScript,
+                ModuleSourceCode::String(code.into()),
+                module_specifier,
+                None,
+            )
+        }))
+    }
+}
+
+// Capture the TypeScript compiler in a non-writable __tanaCompile(src, fileName)
+// and hide the global `ts`. Run once per isolate after the bootstrap, before any
+// contract code; without the compiler (pre-compiled only runtimes) it does nothing.
+pub fn install_compiler(runtime: &mut JsRuntime) -> Result<(), String> {
+    let script = format!(
+        r#"
+        ((ts) => {{
+          if (ts === undefined) return;
+          const meter = {meter};
+          const transpile = ts.transpileModule;
+          const preProcess = ts.preProcessFile;
+          const esnext = ts.ModuleKind.ESNext;
+          const stringify = JSON.stringify;
+
+          // Imports are taken from the output so type-only imports are already gone
+          const compile = (src, fileName) => {{
+            const code = transpile(src, {{
+              fileName,
+              compilerOptions: {{ target: "ES2020", module: esnext }},
+              transformers: {{ after: [meter] }}
+            }}).outputText;
+            const files = preProcess(code, true, true).importedFiles;
+            const imports = [];
+            for (let i = 0; i < files.length; i++) imports[i] = files[i].fileName;
+            return stringify({{ code, imports }});
+          }};
+
+          Object.defineProperty(globalThis, "__tanaCompile", {{
+            value: compile,
+            writable: false,
+            configurable: false,
+            enumerable: false,
+          }});
+          Object.defineProperty(globalThis, "ts", {{ value: undefined, writable: false }});
+        }})(typeof ts === "undefined" ? undefined : ts);
+        "#,
+        meter = gas::METER_TRANSFORMER,
+    );
+
+    runtime
+        .execute_script("tana-compiler.js", ModuleCodeString::from(script))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to install compiler: {}", e))
+}
+
+// Compile a module (TypeScript is transpiled, JS parsed as-is) with metering injected,
+// and list its imports, with the compiler captured by install_compiler
+fn compile_module(runtime: &mut JsRuntime, path: &Path, source: &str) -> Result<(String, Vec<String>), String> {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let script = format!(
+        "__tanaCompile({src}, {file_name})",
+        src = serde_json::to_string(source).unwrap(),
+        file_name = serde_json::to_string(&file_name).unwrap(),
+    );
+
+    let output = eval_string(runtime, "compile-module.ts", &script)
+        .map_err(|e| format!("Failed to compile {}: {}", path.display(), e))?;
+
+    #[derive(serde::Deserialize)]
+    struct Compiled {
+        code: String,
+        imports: Vec<String>,
+    }
+    let compiled: Compiled = serde_json::from_str(&output)
+        .map_err(|e| format!("Failed to compile {}: {}", path.display(), e))?;
+
+    Ok((compiled.code, compiled.imports))
+}
+
+fn eval_string(runtime: &mut JsRuntime, name: &'static str, script: &str) -> Result<String, String> {
+    let value = runtime
+        .execute_script(name, ModuleCodeString::from(script.to_string()))
+        .map_err(|e| e.to_string())?;
+
+    let scope = &mut runtime.handle_scope();
+    let local = deno_core::v8::Local::new(scope, value);
+    Ok(local.to_rust_string_lossy(scope))
+}
+
+fn is_relative(specifier: &str) -> bool {
+    specifier.starts_with("./") || specifier.starts_with("../")
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
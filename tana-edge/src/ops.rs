@@ -0,0 +1,1094 @@
+// Tana ops and the state they share
+//
+// Compiled into the binary and into build.rs (see tana_extension), so this file
+// must only depend on crates listed in both [dependencies] and [build-dependencies].
+
+use std::sync::{Arc, OnceLock};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+
+use deno_core::op2;
+use deno_core::{Extension, OpState};
+
+use crate::context::{self, BlockContext};
+use crate::fetch;
+use crate::fetch_cache;
+use crate::gas;
+use crate::ledger;
+use crate::request_body::{FormValue, RequestBody};
+use crate::storage::{MemoryStorage, Quota, StorageBackend};
+use crate::stream::{ResponseStream, StreamProgress};
+
+// Global storage backend (selected at startup via TANA_STORAGE, see storage.rs)
+pub static STORAGE: OnceLock<Arc<dyn StorageBackend>> = OnceLock::new();
+
+// Uncommitted tana/data changes of the current run (see RunState)
+// Maps keys to Option<String>: Some(value) = set, None = delete
+type Staging = HashMap<String, Option<String>>;
+
+// Storage limits (same as playground, enforced per contract)
+const MAX_KEY_SIZE: usize = 256;
+const MAX_VALUE_SIZE: usize = 10_240;  // 10 KB
+const MAX_TOTAL_SIZE: usize = 102_400; // 100 KB
+const MAX_KEYS: usize = 1000;
+
+// Query limits (anti-abuse)
+const MAX_BATCH_QUERY: usize = 10;
+
+// ========== Ops (shared with tana-runtime) ==========
+
+#[op2]
+fn op_sum(#[serde] nums: Vec<f64>) -> Result<f64, deno_error::JsErrorBox> {
+    Ok(nums.iter().sum())
+}
+
+#[op2(fast)]
+fn op_print_stderr(state: &mut OpState, #[string] msg: String) -> Result<(), deno_error::JsErrorBox> {
+    gas::charge(state, gas::LOG + gas::LOG_BYTE * msg.len() as u64)?;
+    eprint!("{}", msg);
+    Ok(())
+}
+
+// Compute gas: the loader injects __tanaGas(n) into every block of contract code
+#[op2(fast)]
+fn op_gas_statements(state: &mut OpState, #[smi] statements: u32) -> Result<(), deno_error::JsErrorBox> {
+    gas::charge(state, gas::compute_gas(statements as u64))
+}
+
+// fetch(url, init) from tana/utils (see fetch.rs); binary request bodies come
+// in `body`, text bodies in init.body
+#[op2(async)]
+#[serde]
+async fn op_fetch(
+    state: Rc<RefCell<OpState>>,
+    #[string] url: String,
+    #[serde] init: fetch::FetchInit,
+    #[buffer(copy)] body: Vec<u8>
+) -> Result<fetch::FetchResponse, deno_error::JsErrorBox> {
+    let request_size = body.len() + init.body.as_ref().map_or(0, String::len);
+    gas::charge(&state.borrow(), gas::FETCH + gas::FETCH_BYTE * request_size as u64)?;
+
+    // Cache hits cost the same gas as upstream fetches
+    let contract_id = contract_id(&state.borrow());
+    let response = fetch_cache::fetch(&contract_id, &url, init, body).await?;
+
+    gas::charge(&state.borrow(), gas::FETCH_BYTE * response.size as u64)?;
+    Ok(response)
+}
+
+// UTF-8 text of a fetched body (Response.text()); invalid sequences become U+FFFD
+#[op2]
+#[string]
+fn op_decode_utf8(#[buffer] bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+// ========== Data Storage Ops ==========
+
+// Executing contract, from the run's block context (put into OpState by begin_run)
+// All tana/data keys and quotas are scoped by this id
+fn contract_id(state: &OpState) -> String {
+    state.borrow::<BlockContext>().contract_id.clone()
+}
+
+// Execution state of the current run, owned by the isolate's OpState
+// (gas is metered separately, see gas::install_meter)
+#[derive(Default)]
+struct RunState {
+    // tana/data changes not yet committed
+    staging: Staging,
+    // tana/tx changes not yet executed
+    tx_changes: Vec<serde_json::Value>,
+    // tana/block ledger lookups already made, so repeats don't hit the ledger
+    ledger_reads: HashMap<String, serde_json::Value>,
+    io: RunIo,
+}
+
+// HTTP side of a run: the raw request body (Request.text() etc.), when the
+// caller can stream, where a streamed Response body goes (see stream.rs), and
+// the methods the contract handles, for a 405 (see dispatch.rs)
+#[derive(Default)]
+pub struct RunIo {
+    pub body: RequestBody,
+    pub stream: Option<ResponseStream>,
+    pub allow: Vec<&'static str>,
+}
+
+// Start a run in `context`: replaces the previous run's state, so nothing
+// staged by one request is visible to the next on the same isolate
+pub fn begin_run(state: &mut OpState, mut context: BlockContext, io: RunIo) {
+    context.resolve_timestamp();
+    state.put(context);
+    // For the stream limits in the run's watchdog (limits.rs)
+    match io.stream.as_ref() {
+        Some(stream) => state.put(stream.progress()),
+        None => drop(state.try_take::<StreamProgress>()),
+    }
+    state.put(RunState {
+        io,
+        ..RunState::default()
+    });
+}
+
+// Finish a run: closes a streamed response body, or aborts it with `failure`
+// when the run failed after the head was sent
+pub fn end_run(state: &mut OpState, failure: Option<&str>) {
+    let contract_id = state.try_borrow::<BlockContext>().map(|c| c.contract_id.clone()).unwrap_or_default();
+    if let Some(run) = state.try_borrow_mut::<RunState>() {
+        if let (Some(stream), Some(error)) = (run.io.stream.as_mut(), failure) {
+            if stream.fail(error) {
+                eprintln!("[STREAM] aborted contract={} error={}", contract_id, error);
+            }
+        }
+        run.io.stream = None;
+    }
+}
+
+fn run_state(state: &mut OpState) -> &mut RunState {
+    state.borrow_mut::<RunState>()
+}
+
+fn storage() -> Arc<dyn StorageBackend> {
+    STORAGE
+        .get_or_init(|| Arc::new(MemoryStorage::default()))
+        .clone()
+}
+
+fn storage_error(e: String) -> deno_error::JsErrorBox {
+    deno_error::JsErrorBox::new("Error", format!("Storage error: {}", e))
+}
+
+// Run `f` against the current run's staging buffer
+fn with_staging<R>(state: &mut OpState, f: impl FnOnce(&mut Staging) -> R) -> R {
+    f(&mut run_state(state).staging)
+}
+
+#[op2(fast)]
+fn op_data_set(
+    state: &mut OpState,
+    #[string] key: String,
+    #[string] value: String
+) -> Result<(), deno_error::JsErrorBox> {
+    // Validate key size
+    if key.len() > MAX_KEY_SIZE {
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            format!("Key too large: {} bytes (max {})", key.len(), MAX_KEY_SIZE)
+        ));
+    }
+
+    // Validate value size
+    if value.len() > MAX_VALUE_SIZE {
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            format!("Value too large: {} bytes (max {})", value.len(), MAX_VALUE_SIZE)
+        ));
+    }
+
+    gas::charge(state, gas::DATA_WRITE + gas::DATA_WRITE_BYTE * (key.len() + value.len()) as u64)?;
+
+    // Stage the change
+    with_staging(state, |stage| {
+        stage.insert(key, Some(value));
+    });
+
+    Ok(())
+}
+
+#[op2(async)]
+#[serde]
+async fn op_data_get(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String
+) -> Result<Option<String>, deno_error::JsErrorBox> {
+    let contract_id = contract_id(&state.borrow());
+    gas::charge(&state.borrow(), gas::DATA_READ)?;
+
+    // Check staging first, then storage (OpState must not stay borrowed across the await)
+    let staged = with_staging(&mut state.borrow_mut(), |stage| stage.get(&key).cloned());
+    let value = match staged {
+        Some(staged_value) => staged_value,
+        None => storage().get(&contract_id, &key).await.map_err(storage_error)?,
+    };
+
+    let bytes = value.as_ref().map(|v| v.len()).unwrap_or(0) as u64;
+    gas::charge(&state.borrow(), gas::DATA_READ_BYTE * bytes)?;
+    Ok(value)
+}
+
+#[op2(fast)]
+fn op_data_delete(state: &mut OpState, #[string] key: String) -> Result<(), deno_error::JsErrorBox> {
+    gas::charge(state, gas::DATA_DELETE)?;
+
+    // Mark for deletion
+    with_staging(state, |stage| {
+        stage.insert(key, None);
+    });
+
+    Ok(())
+}
+
+#[op2(async)]
+async fn op_data_has(
+    state: Rc<RefCell<OpState>>,
+    #[string] key: String
+) -> Result<bool, deno_error::JsErrorBox> {
+    let contract_id = contract_id(&state.borrow());
+    gas::charge(&state.borrow(), gas::DATA_READ)?;
+
+    // Check staging first
+    let staged = with_staging(&mut state.borrow_mut(), |stage| stage.get(&key).cloned());
+    if let Some(staged_value) = staged {
+        return Ok(staged_value.is_some());
+    }
+
+    // Then check storage
+    let value = storage().get(&contract_id, &key).await.map_err(storage_error)?;
+    Ok(value.is_some())
+}
+
+#[op2(async)]
+#[serde]
+async fn op_data_keys(
+    state: Rc<RefCell<OpState>>,
+    #[string] pattern: Option<String>
+) -> Result<Vec<String>, deno_error::JsErrorBox> {
+    let contract_id = contract_id(&state.borrow());
+    gas::charge(&state.borrow(), gas::DATA_KEYS)?;
+
+    // Get keys from storage
+    let mut all_keys: HashSet<String> = storage()
+        .keys(&contract_id)
+        .await
+        .map_err(storage_error)?
+        .into_iter()
+        .collect();
+
+    // Merge with staging (add new keys, remove deleted ones)
+    with_staging(&mut state.borrow_mut(), |stage| {
+        for (key, value) in stage.iter() {
+            if value.is_none() {
+                all_keys.remove(key);
+            } else {
+                all_keys.insert(key.clone());
+            }
+        }
+    });
+
+    let mut keys: Vec<String> = all_keys.into_iter().collect();
+
+    // Apply pattern filter if provided
+    if let Some(pattern_str) = pattern {
+        let regex_pattern = pattern_str.replace("*", ".*");
+        let regex = regex::Regex::new(&format!("^{}$", regex_pattern))
+            .map_err(|e| deno_error::JsErrorBox::new("Error", format!("Invalid pattern: {}", e)))?;
+        keys.retain(|k| regex.is_match(k));
+    }
+
+    keys.sort();
+    gas::charge(&state.borrow(), gas::DATA_KEYS_ITEM * keys.len() as u64)?;
+    Ok(keys)
+}
+
+#[op2(async)]
+async fn op_data_clear(state: Rc<RefCell<OpState>>) -> Result<(), deno_error::JsErrorBox> {
+    let contract_id = contract_id(&state.borrow());
+    gas::charge(&state.borrow(), gas::DATA_CLEAR)?;
+
+    // Clear storage
+    storage().clear(&contract_id).await.map_err(storage_error)?;
+
+    // Clear staging
+    with_staging(&mut state.borrow_mut(), |stage| stage.clear());
+
+    Ok(())
+}
+
+#[op2(async)]
+async fn op_data_commit(state: Rc<RefCell<OpState>>) -> Result<(), deno_error::JsErrorBox> {
+    let contract_id = contract_id(&state.borrow());
+
+    // Snapshot staged changes (OpState can't stay borrowed across the backend calls)
+    let stage = with_staging(&mut state.borrow_mut(), |stage| stage.clone());
+    if stage.is_empty() {
+        return Ok(());
+    }
+
+    // Charged for the bytes written, before touching the backend
+    let staged_bytes: usize = stage
+        .iter()
+        .map(|(key, value)| key.len() + value.as_ref().map(|v| v.len()).unwrap_or(0))
+        .sum();
+    gas::charge(&state.borrow(), gas::DATA_COMMIT + gas::DATA_COMMIT_BYTE * staged_bytes as u64)?;
+
+    // Quotas are per contract; the backend checks them and commits atomically
+    let quota = Quota { max_bytes: MAX_TOTAL_SIZE, max_keys: MAX_KEYS };
+    storage()
+        .commit(&contract_id, &stage, quota)
+        .await
+        .map_err(storage_error)?
+        .map_err(|e| deno_error::JsErrorBox::new("Error", e))?;
+
+    // Clear committed entries from staging (keeps anything staged meanwhile)
+    with_staging(&mut state.borrow_mut(), |current| {
+        current.retain(|key, value| stage.get(key) != Some(&*value));
+    });
+
+    Ok(())
+}
+
+// ========== Block Context Ops ==========
+
+#[op2(fast)]
+#[bigint]
+fn op_block_get_height(state: &mut OpState) -> Result<u64, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    Ok(state.borrow::<BlockContext>().height)
+}
+
+#[op2(fast)]
+fn op_block_get_timestamp(state: &mut OpState) -> Result<f64, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    // Pinned by begin_run
+    Ok(state.borrow::<BlockContext>().timestamp.unwrap_or(0.0))
+}
+
+#[op2]
+#[string]
+fn op_block_get_hash(state: &mut OpState) -> Result<String, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    Ok(state.borrow::<BlockContext>().hash.clone())
+}
+
+// null for the genesis block (or when the caller didn't say)
+#[op2]
+#[serde]
+fn op_block_get_previous_hash(state: &mut OpState) -> Result<Option<String>, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    Ok(state.borrow::<BlockContext>().previous_hash.clone())
+}
+
+#[op2]
+#[string]
+fn op_block_get_executor(state: &mut OpState) -> Result<String, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    Ok(state.borrow::<BlockContext>().executor.clone())
+}
+
+#[op2]
+#[string]
+fn op_block_get_contract_id(state: &mut OpState) -> Result<String, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    Ok(contract_id(state))
+}
+
+// Ledger account holding the contract's funds; null when it has none
+#[op2]
+#[serde]
+fn op_block_get_contract_account(state: &mut OpState) -> Result<Option<String>, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    Ok(state.borrow::<BlockContext>().contract_account.clone())
+}
+
+#[op2(fast)]
+#[bigint]
+fn op_block_get_gas_limit(state: &mut OpState) -> Result<u64, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    Ok(state.borrow::<BlockContext>().gas_limit)
+}
+
+#[op2(fast)]
+#[bigint]
+fn op_block_get_gas_used(state: &mut OpState) -> Result<u64, deno_error::JsErrorBox> {
+    gas::charge(state, gas::BLOCK_CONTEXT)?;
+    // Includes this call; compute gas is settled whenever V8 yields to the event loop
+    Ok(gas::meter(state).map(|meter| meter.used()).unwrap_or(0))
+}
+
+// ========== Blockchain State Query Ops (kept for compatibility) ==========
+
+#[op2(async)]
+#[serde]
+async fn op_block_get_balance(
+    state: Rc<RefCell<OpState>>,
+    #[serde] user_ids: serde_json::Value,
+    #[string] currency_code: String
+) -> Result<serde_json::Value, deno_error::JsErrorBox> {
+    // Parse input (string or array)
+    let ids: Vec<String> = match user_ids {
+        serde_json::Value::String(s) => vec![s],
+        serde_json::Value::Array(arr) => {
+            arr.into_iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        },
+        _ => return Err(deno_error::JsErrorBox::new("TypeError", "Invalid user_ids")),
+    };
+
+    // Check batch limit
+    if ids.len() > MAX_BATCH_QUERY {
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            format!("Cannot query more than {} balances at once", MAX_BATCH_QUERY)
+        ));
+    }
+
+    gas::charge(&state.borrow(), gas::BLOCK_QUERY + gas::BLOCK_QUERY_ITEM * ids.len() as u64)?;
+
+    // One ledger lookup per user not read yet this run (see ledger.rs)
+    let mut results = Vec::with_capacity(ids.len());
+    for user_id in &ids {
+        let balance = cached_balance(&state, user_id, &currency_code)
+            .await
+            .map_err(|e| ledger_error("balances", e))?;
+        results.push(balance);
+    }
+
+    // Return single value or array based on input
+    if ids.len() == 1 {
+        Ok(results[0].clone())
+    } else {
+        Ok(serde_json::json!(results))
+    }
+}
+
+#[op2(async)]
+#[serde]
+async fn op_block_get_user(
+    state: Rc<RefCell<OpState>>,
+    #[serde] user_ids: serde_json::Value
+) -> Result<serde_json::Value, deno_error::JsErrorBox> {
+    // Parse input (string or array)
+    let ids: Vec<String> = match user_ids {
+        serde_json::Value::String(s) => vec![s],
+        serde_json::Value::Array(arr) => {
+            arr.into_iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        },
+        _ => return Err(deno_error::JsErrorBox::new("TypeError", "Invalid user_ids")),
+    };
+
+    // Check batch limit
+    if ids.len() > MAX_BATCH_QUERY {
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            format!("Cannot query more than {} users at once", MAX_BATCH_QUERY)
+        ));
+    }
+
+    gas::charge(&state.borrow(), gas::BLOCK_QUERY + gas::BLOCK_QUERY_ITEM * ids.len() as u64)?;
+
+    // One ledger lookup per id not read yet this run (see ledger.rs)
+    let mut results = Vec::with_capacity(ids.len());
+    for user_id in &ids {
+        let key = format!("user:{}", user_id);
+        let user = match cached_ledger_read(&state, &key) {
+            Some(user) => user,
+            None => {
+                let user = ledger::user(user_id)
+                    .await
+                    .map_err(|e| ledger_error("users", e))?;
+                remember_ledger_read(&state, key, serde_json::json!(user))
+            },
+        };
+        results.push(user);
+    }
+
+    // Return single value or array based on input (null when not found)
+    if ids.len() == 1 {
+        Ok(results[0].clone())
+    } else {
+        Ok(serde_json::json!(results))
+    }
+}
+
+#[op2(async)]
+#[serde]
+async fn op_block_get_transaction(
+    state: Rc<RefCell<OpState>>,
+    #[serde] tx_ids: serde_json::Value
+) -> Result<serde_json::Value, deno_error::JsErrorBox> {
+    // Parse input (string or array)
+    let ids: Vec<String> = match tx_ids {
+        serde_json::Value::String(s) => vec![s],
+        serde_json::Value::Array(arr) => {
+            arr.into_iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        },
+        _ => return Err(deno_error::JsErrorBox::new("TypeError", "Invalid tx_ids")),
+    };
+
+    // Check batch limit
+    if ids.len() > MAX_BATCH_QUERY {
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            format!("Cannot query more than {} transactions at once", MAX_BATCH_QUERY)
+        ));
+    }
+
+    gas::charge(&state.borrow(), gas::BLOCK_QUERY + gas::BLOCK_QUERY_ITEM * ids.len() as u64)?;
+
+    // One ledger lookup per id not read yet this run (see ledger.rs)
+    let mut results = Vec::with_capacity(ids.len());
+    for tx_id in &ids {
+        let key = format!("transaction:{}", tx_id);
+        let transaction = match cached_ledger_read(&state, &key) {
+            Some(transaction) => transaction,
+            None => {
+                let transaction = ledger::transaction(tx_id)
+                    .await
+                    .map_err(|e| ledger_error("transactions", e))?;
+                remember_ledger_read(&state, key, serde_json::json!(transaction))
+            },
+        };
+        results.push(transaction);
+    }
+
+    // Return single value or array based on input (null when not found)
+    if ids.len() == 1 {
+        Ok(results[0].clone())
+    } else {
+        Ok(serde_json::json!(results))
+    }
+}
+
+#[op2(async)]
+#[serde]
+async fn op_block_get_block(
+    state: Rc<RefCell<OpState>>,
+    #[serde] heights: serde_json::Value
+) -> Result<serde_json::Value, deno_error::JsErrorBox> {
+    // Parse input (height or array of heights)
+    let heights: Vec<u64> = match heights {
+        serde_json::Value::Number(n) => match n.as_u64() {
+            Some(height) => vec![height],
+            None => return Err(deno_error::JsErrorBox::new("TypeError", "Invalid heights")),
+        },
+        // Reject bad entries instead of dropping them, so results[i] matches heights[i]
+        serde_json::Value::Array(arr) => arr
+            .iter()
+            .map(|v| v.as_u64())
+            .collect::<Option<Vec<u64>>>()
+            .ok_or_else(|| deno_error::JsErrorBox::new("TypeError", "Block heights must be non-negative integers"))?,
+        _ => return Err(deno_error::JsErrorBox::new("TypeError", "Invalid heights")),
+    };
+    let single = heights.len() == 1;
+
+    // Check batch limit
+    if heights.len() > MAX_BATCH_QUERY {
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            format!("Cannot query more than {} blocks at once", MAX_BATCH_QUERY)
+        ));
+    }
+
+    gas::charge(&state.borrow(), gas::BLOCK_QUERY + gas::BLOCK_QUERY_ITEM * heights.len() as u64)?;
+
+    // One ledger lookup per height not read yet this run (see ledger.rs)
+    let mut results = Vec::with_capacity(heights.len());
+    for height in heights {
+        let key = format!("block:{}", height);
+        let block = match cached_ledger_read(&state, &key) {
+            Some(block) => block,
+            None => {
+                let block = ledger::block(height)
+                    .await
+                    .map_err(|e| ledger_error("blocks", e))?;
+                remember_ledger_read(&state, key, serde_json::json!(block))
+            },
+        };
+        results.push(block);
+    }
+
+    // Return single value or array based on input (null when not found)
+    if single {
+        Ok(results[0].clone())
+    } else {
+        Ok(serde_json::json!(results))
+    }
+}
+
+// Same block for the whole run, like every other tana/block read
+#[op2(async)]
+#[serde]
+async fn op_block_get_latest_block(
+    state: Rc<RefCell<OpState>>
+) -> Result<serde_json::Value, deno_error::JsErrorBox> {
+    gas::charge(&state.borrow(), gas::BLOCK_QUERY + gas::BLOCK_QUERY_ITEM)?;
+
+    let key = "block:latest".to_string();
+    if let Some(block) = cached_ledger_read(&state, &key) {
+        return Ok(block);
+    }
+
+    let block = ledger::latest_block()
+        .await
+        .map_err(|e| ledger_error("latest block", e))?;
+    if let Some(height) = block.as_ref().and_then(|b| b["height"].as_f64()) {
+        remember_ledger_read(&state, format!("block:{}", height as u64), serde_json::json!(block));
+    }
+    Ok(remember_ledger_read(&state, key, serde_json::json!(block)))
+}
+
+// Per-run read cache for tana/block lookups, keyed like "user:<id>"
+fn cached_ledger_read(state: &Rc<RefCell<OpState>>, key: &str) -> Option<serde_json::Value> {
+    run_state(&mut state.borrow_mut()).ledger_reads.get(key).cloned()
+}
+
+fn remember_ledger_read(state: &Rc<RefCell<OpState>>, key: String, value: serde_json::Value) -> serde_json::Value {
+    run_state(&mut state.borrow_mut()).ledger_reads.insert(key, value.clone());
+    value
+}
+
+// A user's balance through the read cache (the ledger matches currencies case-insensitively)
+async fn cached_balance(
+    state: &Rc<RefCell<OpState>>,
+    user_id: &str,
+    currency_code: &str,
+) -> Result<serde_json::Value, ledger::LedgerError> {
+    let key = format!("balance:{}:{}", currency_code.to_uppercase(), user_id);
+    if let Some(balance) = cached_ledger_read(state, &key) {
+        return Ok(balance);
+    }
+    let balance = ledger::balance(user_id, currency_code).await?;
+    Ok(remember_ledger_read(state, key, serde_json::json!(balance)))
+}
+
+// Ledger failure as a JS error ("Failed to fetch users: ...")
+fn ledger_error(what: &str, error: ledger::LedgerError) -> deno_error::JsErrorBox {
+    let action = match error {
+        ledger::LedgerError::Parse(_) => "parse",
+        _ => "fetch",
+    };
+    deno_error::JsErrorBox::new("Error", format!("Failed to {} {}: {}", action, what, error))
+}
+
+// ========== Transaction Staging Ops ==========
+
+#[op2(fast)]
+fn op_tx_transfer(
+    state: &mut OpState,
+    #[string] from: String,
+    #[string] to: String,
+    amount: f64,
+    #[string] currency: String
+) -> Result<(), deno_error::JsErrorBox> {
+    if from == to {
+        return Err(deno_error::JsErrorBox::new("Error", "Cannot transfer to self"));
+    }
+    if !amount.is_finite() {
+        return Err(deno_error::JsErrorBox::new("Error", "Amount must be a finite number"));
+    }
+    if amount <= 0.0 {
+        return Err(deno_error::JsErrorBox::new("Error", "Amount must be positive"));
+    }
+
+    // Only the caller's funds or the contract's own can be moved
+    let block = state.borrow::<BlockContext>();
+    if !block.may_debit(&from) {
+        let caller = if block.executor.is_empty() { "none".to_string() } else { block.executor.clone() };
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            format!(
+                "Not authorized to transfer from {}: contracts can only debit the caller ({}) or their own account",
+                from, caller
+            )
+        ));
+    }
+
+    gas::charge(state, gas::TX_STAGE)?;
+
+    let change = serde_json::json!({
+        "type": "transfer",
+        "from": from,
+        "to": to,
+        "amount": amount,
+        "currency": currency
+    });
+
+    run_state(state).tx_changes.push(change);
+    Ok(())
+}
+
+#[op2(fast)]
+fn op_tx_set_balance(
+    state: &mut OpState,
+    #[string] user_id: String,
+    amount: f64,
+    #[string] currency: String
+) -> Result<(), deno_error::JsErrorBox> {
+    if !amount.is_finite() {
+        return Err(deno_error::JsErrorBox::new("Error", "Amount must be a finite number"));
+    }
+    if amount < 0.0 {
+        return Err(deno_error::JsErrorBox::new("Error", "Balance cannot be negative"));
+    }
+
+    if !context::is_system_contract(&contract_id(state)) {
+        return Err(deno_error::JsErrorBox::new(
+            "Error",
+            "Not authorized: setBalance is restricted to system contracts"
+        ));
+    }
+
+    gas::charge(state, gas::TX_STAGE)?;
+
+    let change = serde_json::json!({
+        "type": "balance_update",
+        "userId": user_id,
+        "amount": amount,
+        "currency": currency
+    });
+
+    run_state(state).tx_changes.push(change);
+    Ok(())
+}
+
+#[op2]
+#[serde]
+fn op_tx_get_changes(state: &mut OpState) -> serde_json::Value {
+    serde_json::Value::Array(run_state(state).tx_changes.clone())
+}
+
+// Settle the staged changes on the ledger as one atomic batch (see ledger::settle)
+// A rejected or failed settlement puts the changes back in front of anything
+// staged meanwhile, so the contract can inspect them or try again
+#[op2(async)]
+#[serde]
+async fn op_tx_execute(state: Rc<RefCell<OpState>>) -> Result<serde_json::Value, deno_error::JsErrorBox> {
+    let (changes, gas_used, context) = {
+        let mut state = state.borrow_mut();
+        let changes = std::mem::take(&mut run_state(&mut state).tx_changes);
+        // Plus a balance lookup per sender for check_balances
+        let gas_used = gas::TX_EXECUTE
+            + gas::TX_EXECUTE_CHANGE * changes.len() as u64
+            + gas::BLOCK_QUERY_ITEM * senders(&changes) as u64;
+
+        // Out of gas aborts the run
+        gas::charge(&state, gas_used)?;
+
+        (changes, gas_used, state.borrow::<BlockContext>().clone())
+    };
+
+    if changes.is_empty() {
+        return Ok(serde_json::json!({
+            "success": true,
+            "changes": [],
+            "transactionIds": [],
+            "gasUsed": gas_used,
+            "error": null
+        }));
+    }
+
+    // Refuse overdrafts here, with the change that causes them, before the ledger sees the batch
+    let outcome = match check_balances(&state, &changes).await {
+        Ok(None) => ledger::settle(&context.contract_id, &context.executor, &changes).await,
+        Ok(Some((error, index))) => Ok(ledger::Settlement::Rejected { error, index: Some(index) }),
+        Err(e) => Err(e),
+    };
+
+    let mut state = state.borrow_mut();
+    let run = run_state(&mut state);
+    let (error, failed_index) = match outcome {
+        Ok(ledger::Settlement::Settled(records)) => {
+            eprintln!("[LEDGER] settled {} change(s) for contract={}", changes.len(), context.contract_id);
+
+            // Balances read earlier in the run are stale now
+            run.ledger_reads.retain(|key, _| !key.starts_with("balance:"));
+
+            let ids: Vec<serde_json::Value> = records.iter().map(|record| record["id"].clone()).collect();
+            return Ok(serde_json::json!({
+                "success": true,
+                "changes": changes,
+                "transactionIds": ids,
+                "gasUsed": gas_used,
+                "error": null
+            }));
+        },
+        Ok(ledger::Settlement::Rejected { error, index }) => (error, index),
+        Ok(ledger::Settlement::Unresolved { batch, error }) => {
+            let error = format!("Settlement of batch {} is unresolved: {}", batch, error);
+            eprintln!("[LEDGER] {} (contract={})", error, context.contract_id);
+
+            // It may have been applied, so the changes aren't staged again
+            run.ledger_reads.retain(|key, _| !key.starts_with("balance:"));
+
+            return Ok(serde_json::json!({
+                "success": false,
+                "changes": changes,
+                "transactionIds": [],
+                "gasUsed": gas_used,
+                "error": error,
+                "failedIndex": null
+            }));
+        },
+        Err(e) => (format!("Failed to settle transaction: {}", e), None),
+    };
+    eprintln!("[LEDGER] settlement failed for contract={}: {}", context.contract_id, error);
+
+    // Roll back: the changes are staged again, ahead of anything staged meanwhile
+    let staged_meanwhile = std::mem::replace(&mut run.tx_changes, changes.clone());
+    run.tx_changes.extend(staged_meanwhile);
+
+    Ok(serde_json::json!({
+        "success": false,
+        "changes": changes,
+        "transactionIds": [],
+        "gasUsed": gas_used,
+        "error": error,
+        "failedIndex": failed_index
+    }))
+}
+
+// Ledger amounts have 8 decimals; balances are checked in those units so
+// sums like 0.1 + 0.2 don't overdraw a balance of 0.3
+const AMOUNT_UNITS: f64 = 100_000_000.0;
+
+fn amount_units(amount: f64) -> i128 {
+    (amount * AMOUNT_UNITS).round() as i128
+}
+
+fn format_units(units: i128) -> String {
+    (units as f64 / AMOUNT_UNITS).to_string()
+}
+
+// Distinct (sender, currency) pairs of the staged transfers
+fn senders(changes: &[serde_json::Value]) -> usize {
+    changes
+        .iter()
+        .filter(|change| change["type"] == "transfer")
+        .map(|change| (change["from"].as_str(), change["currency"].as_str().map(str::to_uppercase)))
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+// Replay the staged changes in order against the senders' ledger balances
+// (setBalance replaces a balance, transfers move amounts between users)
+// Balances are read through the run's read cache; op_tx_execute charges the gas
+// Returns the first change that would overdraw or has no usable amount, as (error, index)
+// A balance the ledger reports that is not a number fails the check as a parse error
+async fn check_balances(
+    state: &Rc<RefCell<OpState>>,
+    changes: &[serde_json::Value],
+) -> Result<Option<(String, usize)>, ledger::LedgerError> {
+    // Per (user, currency): the ledger balance (None until a transfer needs it,
+    // or the staged setBalance amount) and the net change since
+    let mut balances: HashMap<(String, String), (Option<i128>, i128)> = HashMap::new();
+
+    for (index, change) in changes.iter().enumerate() {
+        let currency = change["currency"].as_str().unwrap_or_default().to_uppercase();
+        let amount = match change["amount"].as_f64().filter(|amount| amount.is_finite()) {
+            Some(amount) => amount_units(amount),
+            None => {
+                let error = format!("Change {}: amount {} is missing or not a number", index, change["amount"]);
+                return Ok(Some((error, index)));
+            },
+        };
+
+        match change["type"].as_str() {
+            Some("transfer") => {
+                let from = change["from"].as_str().unwrap_or_default().to_string();
+                let to = change["to"].as_str().unwrap_or_default().to_string();
+
+                let sender = balances.entry((from.clone(), currency.clone())).or_insert((None, 0));
+                if sender.0.is_none() {
+                    let balance = cached_balance(state, &from, &currency).await?;
+                    let units = balance.as_f64().filter(|balance| balance.is_finite()).ok_or_else(|| {
+                        ledger::LedgerError::Parse(format!("balance of {} {} is not a number: {}", from, currency, balance))
+                    })?;
+                    sender.0 = Some(amount_units(units));
+                }
+                let available = sender.0.unwrap_or(0) + sender.1;
+                if available < amount {
+                    return Ok(Some((
+                        format!(
+                            "Change {}: transfer of {} {} from {} to {} exceeds the available balance of {} {}",
+                            index,
+                            format_units(amount),
+                            currency,
+                            from,
+                            to,
+                            format_units(available),
+                            currency
+                        ),
+                        index,
+                    )));
+                }
+                sender.1 -= amount;
+                balances.entry((to, currency)).or_insert((None, 0)).1 += amount;
+            },
+            Some("balance_update") => {
+                let user = change["userId"].as_str().unwrap_or_default().to_string();
+                balances.insert((user, currency), (Some(amount), 0));
+            },
+            _ => {},
+        }
+    }
+
+    Ok(None)
+}
+
+// ========== tana/net request body ==========
+
+#[op2]
+#[buffer]
+fn op_request_body(state: &mut OpState) -> Result<Vec<u8>, deno_error::JsErrorBox> {
+    let bytes = run_state(state).io.body.bytes.clone();
+    gas::charge(state, gas::REQUEST_BODY + gas::REQUEST_BODY_BYTE * bytes.len() as u64)?;
+    Ok(bytes)
+}
+
+#[op2]
+#[string]
+fn op_request_text(state: &mut OpState) -> Result<String, deno_error::JsErrorBox> {
+    let text = run_state(state).io.body.text();
+    gas::charge(state, gas::REQUEST_BODY + gas::REQUEST_BODY_BYTE * text.len() as u64)?;
+    Ok(text)
+}
+
+// [name, value] pairs; Request.formData() groups repeated names
+#[op2]
+#[serde]
+fn op_request_form(state: &mut OpState) -> Result<Vec<(String, FormValue)>, deno_error::JsErrorBox> {
+    let body = &run_state(state).io.body;
+    let form = body.form();
+    let size = body.bytes.len() as u64;
+    gas::charge(state, gas::REQUEST_BODY + gas::REQUEST_BODY_BYTE * size)?;
+    form.map_err(|e| deno_error::JsErrorBox::new("TypeError", e))
+}
+
+// ========== tana/net response streaming ==========
+
+// Send a streamed Response's status and headers; false when the client is gone
+// Throws when the pool's open-stream slots are all taken
+#[op2]
+fn op_stream_start(
+    state: &mut OpState,
+    #[serde] head: serde_json::Value
+) -> Result<bool, deno_error::JsErrorBox> {
+    gas::charge(state, gas::STREAM_CHUNK)?;
+    match run_state(state).io.stream.as_mut() {
+        Some(stream) => stream.start(head).map_err(|e| deno_error::JsErrorBox::new("Error", e)),
+        None => Ok(false),
+    }
+}
+
+#[op2(async)]
+async fn op_stream_write(
+    state: Rc<RefCell<OpState>>,
+    #[buffer(copy)] chunk: Vec<u8>
+) -> Result<bool, deno_error::JsErrorBox> {
+    stream_write(state, chunk).await
+}
+
+#[op2(async)]
+async fn op_stream_write_text(
+    state: Rc<RefCell<OpState>>,
+    #[string] chunk: String
+) -> Result<bool, deno_error::JsErrorBox> {
+    stream_write(state, chunk.into_bytes()).await
+}
+
+// Waits while the client is behind; false once it has disconnected
+async fn stream_write(state: Rc<RefCell<OpState>>, chunk: Vec<u8>) -> Result<bool, deno_error::JsErrorBox> {
+    let sender = {
+        let mut state = state.borrow_mut();
+        gas::charge(&state, gas::STREAM_CHUNK + gas::STREAM_BYTE * chunk.len() as u64)?;
+        run_state(&mut state).io.stream.as_ref().map(ResponseStream::sender)
+    };
+
+    match sender {
+        Some(sender) => Ok(sender.send(chunk).await),
+        None => Ok(false),
+    }
+}
+
+// ========== Extension ==========
+
+// Every tana op, in registration order
+// build.rs registers this same extension when creating the startup snapshot,
+// so the op list must be identical at build time and at runtime
+pub fn tana_extension() -> Extension {
+    const OP_SUM: deno_core::OpDecl = op_sum();
+    const OP_PRINT_STDERR: deno_core::OpDecl = op_print_stderr();
+    const OP_GAS_STATEMENTS: deno_core::OpDecl = op_gas_statements();
+    const OP_FETCH: deno_core::OpDecl = op_fetch();
+    const OP_DECODE_UTF8: deno_core::OpDecl = op_decode_utf8();
+    const OP_DATA_SET: deno_core::OpDecl = op_data_set();
+    const OP_DATA_GET: deno_core::OpDecl = op_data_get();
+    const OP_DATA_DELETE: deno_core::OpDecl = op_data_delete();
+    const OP_DATA_HAS: deno_core::OpDecl = op_data_has();
+    const OP_DATA_KEYS: deno_core::OpDecl = op_data_keys();
+    const OP_DATA_CLEAR: deno_core::OpDecl = op_data_clear();
+    const OP_DATA_COMMIT: deno_core::OpDecl = op_data_commit();
+    const OP_BLOCK_GET_HEIGHT: deno_core::OpDecl = op_block_get_height();
+    const OP_BLOCK_GET_TIMESTAMP: deno_core::OpDecl = op_block_get_timestamp();
+    const OP_BLOCK_GET_HASH: deno_core::OpDecl = op_block_get_hash();
+    const OP_BLOCK_GET_PREVIOUS_HASH: deno_core::OpDecl = op_block_get_previous_hash();
+    const OP_BLOCK_GET_EXECUTOR: deno_core::OpDecl = op_block_get_executor();
+    const OP_BLOCK_GET_CONTRACT_ID: deno_core::OpDecl = op_block_get_contract_id();
+    const OP_BLOCK_GET_CONTRACT_ACCOUNT: deno_core::OpDecl = op_block_get_contract_account();
+    const OP_BLOCK_GET_GAS_LIMIT: deno_core::OpDecl = op_block_get_gas_limit();
+    const OP_BLOCK_GET_GAS_USED: deno_core::OpDecl = op_block_get_gas_used();
+    const OP_BLOCK_GET_BALANCE: deno_core::OpDecl = op_block_get_balance();
+    const OP_BLOCK_GET_USER: deno_core::OpDecl = op_block_get_user();
+    const OP_BLOCK_GET_TRANSACTION: deno_core::OpDecl = op_block_get_transaction();
+    const OP_BLOCK_GET_BLOCK: deno_core::OpDecl = op_block_get_block();
+    const OP_BLOCK_GET_LATEST_BLOCK: deno_core::OpDecl = op_block_get_latest_block();
+    const OP_TX_TRANSFER: deno_core::OpDecl = op_tx_transfer();
+    const OP_TX_SET_BALANCE: deno_core::OpDecl = op_tx_set_balance();
+    const OP_TX_GET_CHANGES: deno_core::OpDecl = op_tx_get_changes();
+    const OP_TX_EXECUTE: deno_core::OpDecl = op_tx_execute();
+    const OP_REQUEST_BODY: deno_core::OpDecl = op_request_body();
+    const OP_REQUEST_TEXT: deno_core::OpDecl = op_request_text();
+    const OP_REQUEST_FORM: deno_core::OpDecl = op_request_form();
+    const OP_STREAM_START: deno_core::OpDecl = op_stream_start();
+    const OP_STREAM_WRITE: deno_core::OpDecl = op_stream_write();
+    const OP_STREAM_WRITE_TEXT: deno_core::OpDecl = op_stream_write_text();
+
+    Extension {
+        name: "tana_ext",
+        ops: std::borrow::Cow::Borrowed(&[
+            OP_SUM,
+            OP_PRINT_STDERR,
+            OP_GAS_STATEMENTS,
+            OP_FETCH,
+            OP_DECODE_UTF8,
+            OP_DATA_SET,
+            OP_DATA_GET,
+            OP_DATA_DELETE,
+            OP_DATA_HAS,
+            OP_DATA_KEYS,
+            OP_DATA_CLEAR,
+            OP_DATA_COMMIT,
+            OP_BLOCK_GET_HEIGHT,
+            OP_BLOCK_GET_TIMESTAMP,
+            OP_BLOCK_GET_HASH,
+            OP_BLOCK_GET_PREVIOUS_HASH,
+            OP_BLOCK_GET_EXECUTOR,
+            OP_BLOCK_GET_CONTRACT_ID,
+            OP_BLOCK_GET_CONTRACT_ACCOUNT,
+            OP_BLOCK_GET_GAS_LIMIT,
+            OP_BLOCK_GET_GAS_USED,
+            OP_BLOCK_GET_BALANCE,
+            OP_BLOCK_GET_USER,
+            OP_BLOCK_GET_TRANSACTION,
+            OP_BLOCK_GET_BLOCK,
+            OP_BLOCK_GET_LATEST_BLOCK,
+            OP_TX_TRANSFER,
+            OP_TX_SET_BALANCE,
+            OP_TX_GET_CHANGES,
+            OP_TX_EXECUTE,
+            OP_REQUEST_BODY,
+            OP_REQUEST_TEXT,
+            OP_REQUEST_FORM,
+            OP_STREAM_START,
+            OP_STREAM_WRITE,
+            OP_STREAM_WRITE_TEXT,
+        ]),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,403 @@
+// Pool of warm, pre-bootstrapped V8 isolates
+//
+// Each worker thread owns one JsRuntime (V8 isolates are !Send) that already
+// has the TypeScript compiler and tana modules loaded, so a warm request skips
+// runtime creation and bootstrap entirely.
+//
+// Isolation between requests:
+// - an isolate only ever runs one contract: it is bound to the first contract
+//   it runs, and a worker whose isolate is bound to another contract drops it
+//   and warms a fresh one for the request, so nothing one contract left behind
+//   (in built-ins the checks below don't cover, module state, the TypeScript
+//   compiler) can reach another
+// - globals added by a contract are deleted after every request
+// - if a contract replaced a baseline global, touched an intrinsic prototype
+//   (including iterator, generator and typed array prototypes) or a tana
+//   module, the isolate is thrown away instead of reused
+// - any failed run (including one stopped by an execution limit) also throws
+//   the isolate away
+// - every isolate is recycled after TANA_POOL_MAX_REQUESTS requests, or once it
+//   has loaded TANA_POOL_MAX_MODULES contract modules (each run's modules stay in
+//   V8's module map, see loader.rs)
+//
+// A streamed Response keeps its worker until the stream ends, so at most
+// TANA_POOL_MAX_STREAMS streams (default half the workers) are open at once and
+// the rest of the pool stays free for ordinary requests.
+
+use std::env;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use deno_core::{JsRuntime, ModuleCodeString};
+use tokio::sync::{oneshot, Semaphore};
+
+use crate::context::BlockContext;
+use crate::limits::ExecutionError;
+use crate::loader::TanaModuleLoader;
+use crate::ops::RunIo;
+use crate::request::RequestContext;
+
+// Defaults (override with TANA_POOL_SIZE / TANA_POOL_MAX_REQUESTS / TANA_POOL_MAX_MODULES / TANA_POOL_MAX_STREAMS)
+const DEFAULT_MAX_REQUESTS: usize = 1000;
+const DEFAULT_MAX_MODULES: usize = 2000;
+
+// Installed once per isolate after bootstrap. Captures a baseline of the global
+// object and the objects contracts must not mutate, and defines __tanaReset()
+// which deletes globals added since and reports whether the isolate is clean.
+const SEAL_SCRIPT: &str = r#"
+(function () {
+  const watched = [
+    Object, Object.prototype, Function.prototype, Array, Array.prototype,
+    String.prototype, Number.prototype, Boolean.prototype, Symbol.prototype,
+    BigInt.prototype, Promise, Promise.prototype, RegExp.prototype,
+    Map.prototype, Set.prototype, WeakMap.prototype, WeakSet.prototype,
+    Date.prototype, Error.prototype, JSON, Math, Reflect,
+  ];
+  // Intrinsics only reachable through other objects, which the compiler
+  // (__tanaCompile, see loader.rs) relies on as much as contracts do
+  const proto = Object.getPrototypeOf;
+  const arrayIterator = proto([][Symbol.iterator]());
+  const TypedArray = proto(Uint8Array);
+  watched.push(
+    arrayIterator, proto(arrayIterator),
+    proto(new Map()[Symbol.iterator]()), proto(new Set()[Symbol.iterator]()),
+    proto(''[Symbol.iterator]()), proto(/x/[Symbol.matchAll]('')),
+    TypedArray, TypedArray.prototype, Uint8Array.prototype, ArrayBuffer.prototype,
+    proto(function* () {}), proto(function* () {}).prototype,
+    proto(async function () {}), proto(async function* () {}).prototype,
+  );
+  for (const mod of Object.values(tanaModules)) {
+    watched.push(mod);
+    for (const value of Object.values(mod)) {
+      if (value !== null && (typeof value === 'object' || typeof value === 'function')) {
+        watched.push(value);
+        if (typeof value === 'function' && value.prototype) watched.push(value.prototype);
+      }
+    }
+  }
+
+  const describe = (obj) => Reflect.ownKeys(obj).map((key) => {
+    const d = Object.getOwnPropertyDescriptor(obj, key);
+    return [key, d.value, d.get, d.set];
+  });
+  const same = (a, b) => a.length === b.length && a.every((entry, i) =>
+    entry[0] === b[i][0] && Object.is(entry[1], b[i][1]) &&
+    entry[2] === b[i][2] && entry[3] === b[i][3]);
+
+  let globals;
+  let intrinsics;
+
+  Object.defineProperty(globalThis, '__tanaReset', {
+    value: function () {
+      let clean = true;
+      const current = new Set(Reflect.ownKeys(globalThis));
+
+      for (const key of current) {
+        if (!globals.has(key)) {
+          if (!delete globalThis[key]) clean = false;
+          continue;
+        }
+        const before = globals.get(key);
+        const now = Object.getOwnPropertyDescriptor(globalThis, key);
+        if (!Object.is(before.value, now.value) || before.get !== now.get || before.set !== now.set) {
+          clean = false;
+        }
+      }
+      for (const key of globals.keys()) {
+        if (!current.has(key)) clean = false;
+      }
+      for (const [obj, snapshot] of intrinsics) {
+        if (!same(snapshot, describe(obj))) clean = false;
+      }
+
+      return clean;
+    },
+    writable: false,
+    configurable: false,
+  });
+
+  globals = new Map(Reflect.ownKeys(globalThis).map((key) =>
+    [key, Object.getOwnPropertyDescriptor(globalThis, key)]));
+  intrinsics = watched.map((obj) => [obj, describe(obj)]);
+})();
+"#;
+
+struct Job {
+    context: BlockContext,
+    contract_path: PathBuf,
+    contract_source: String,
+    request: RequestContext,
+    io: RunIo,
+    reply: oneshot::Sender<Result<serde_json::Value, ExecutionError>>,
+}
+
+pub struct IsolatePool {
+    jobs: mpsc::Sender<Job>,
+    size: usize,
+    max_requests: usize,
+    max_modules: usize,
+    max_streams: usize,
+    // Open-stream slots (see stream.rs)
+    streams: Arc<Semaphore>,
+}
+
+impl IsolatePool {
+    // TANA_POOL_SIZE=0 disables pooling (fresh isolate per request)
+    // Must be called from inside the tokio runtime (ops run on its handle)
+    pub fn from_env() -> Option<IsolatePool> {
+        let default_size = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        let size = env::var("TANA_POOL_SIZE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(default_size);
+        let max_requests = env::var("TANA_POOL_MAX_REQUESTS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_MAX_REQUESTS);
+        let max_modules = env::var("TANA_POOL_MAX_MODULES")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_MAX_MODULES);
+        let max_streams = env::var("TANA_POOL_MAX_STREAMS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or((size / 2).max(1));
+
+        if size == 0 {
+            return None;
+        }
+
+        Some(IsolatePool::new(size, max_requests, max_modules, max_streams))
+    }
+
+    pub fn new(size: usize, max_requests: usize, max_modules: usize, max_streams: usize) -> IsolatePool {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+        let handle = tokio::runtime::Handle::current();
+
+        for worker_id in 0..size {
+            let rx = rx.clone();
+            let handle = handle.clone();
+            thread::Builder::new()
+                .name(format!("tana-isolate-{}", worker_id))
+                .spawn(move || worker_loop(worker_id, rx, handle, max_requests, max_modules))
+                .expect("Failed to spawn isolate worker");
+        }
+
+        IsolatePool {
+            jobs: tx,
+            size,
+            max_requests,
+            max_modules,
+            max_streams,
+            streams: Arc::new(Semaphore::new(max_streams)),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn max_requests(&self) -> usize {
+        self.max_requests
+    }
+
+    pub fn max_modules(&self) -> usize {
+        self.max_modules
+    }
+
+    pub fn max_streams(&self) -> usize {
+        self.max_streams
+    }
+
+    // Run a contract on the next free warm isolate
+    pub async fn run(
+        &self,
+        context: BlockContext,
+        contract_path: PathBuf,
+        contract_source: String,
+        request: RequestContext,
+        mut io: RunIo,
+    ) -> Result<serde_json::Value, ExecutionError> {
+        if let Some(stream) = io.stream.as_mut() {
+            stream.limit(self.streams.clone());
+        }
+
+        let (reply, response) = oneshot::channel();
+        let job = Job {
+            context,
+            contract_path,
+            contract_source,
+            request,
+            io,
+            reply,
+        };
+
+        self.jobs
+            .send(job)
+            .map_err(|_| ExecutionError::Failed("Isolate pool is shut down".to_string()))?;
+
+        response
+            .await
+            .map_err(|_| ExecutionError::Failed("Isolate worker dropped the request".to_string()))?
+    }
+}
+
+// A bootstrapped runtime, the contract it is bound to (empty until its first
+// run) and how many requests it has served
+struct WarmIsolate {
+    runtime: JsRuntime,
+    contract_id: String,
+    requests: usize,
+}
+
+fn warm_isolate() -> Result<WarmIsolate, String> {
+    // Full bootstrap so the isolate serves both .ts and pre-compiled .js contracts
+    let mut runtime = crate::create_runtime()?;
+    runtime
+        .execute_script("tana-seal.js", ModuleCodeString::from(SEAL_SCRIPT.to_string()))
+        .map_err(|e| format!("Failed to seal isolate: {}", e))?;
+
+    Ok(WarmIsolate {
+        runtime,
+        contract_id: String::new(),
+        requests: 0,
+    })
+}
+
+// Contract modules loaded into the isolate's module map so far
+fn module_count(runtime: &JsRuntime) -> usize {
+    let state = runtime.op_state();
+    let state = state.borrow();
+    state.borrow::<Rc<TanaModuleLoader>>().module_count()
+}
+
+// Delete per-request globals; Ok(false) means the isolate must not be reused
+fn reset_isolate(runtime: &mut JsRuntime) -> Result<bool, String> {
+    let clean = runtime
+        .execute_script("tana-reset.js", ModuleCodeString::from("__tanaReset()".to_string()))
+        .map_err(|e| format!("Failed to reset isolate: {}", e))?;
+
+    let scope = &mut runtime.handle_scope();
+    let local = deno_core::v8::Local::new(scope, clean);
+    Ok(local.is_true())
+}
+
+fn worker_loop(
+    worker_id: usize,
+    jobs: Arc<Mutex<mpsc::Receiver<Job>>>,
+    handle: tokio::runtime::Handle,
+    max_requests: usize,
+    max_modules: usize,
+) {
+    let mut slot: Option<WarmIsolate> = None;
+
+    loop {
+        // Warm the next isolate before waiting, so the request doesn't pay for it
+        if slot.is_none() {
+            slot = warm(worker_id);
+        }
+
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return, // Pool dropped
+        };
+
+        // Code from one contract never runs in an isolate another contract used.
+        // The old isolate goes before its replacement is created: isolates on a
+        // thread must be dropped in the reverse order they were created in
+        if let Some(isolate) = slot.as_ref() {
+            if !isolate.contract_id.is_empty() && isolate.contract_id != job.context.contract_id {
+                eprintln!(
+                    "[POOL] worker={} dropping isolate of contract={} for contract={}",
+                    worker_id,
+                    isolate.contract_id,
+                    job.context.contract_id
+                );
+                drop(slot.take());
+                slot = warm(worker_id);
+            }
+        }
+
+        // Warming failed above: fall back to a cold isolate for this request
+        let isolate = match slot.as_mut() {
+            Some(isolate) => isolate,
+            None => {
+                let result = handle.block_on(crate::run_contract(
+                    job.context,
+                    &job.contract_path,
+                    &job.contract_source,
+                    job.request,
+                    job.io,
+                ));
+                let _ = job.reply.send(result);
+                continue;
+            },
+        };
+        if isolate.contract_id.is_empty() {
+            isolate.contract_id = job.context.contract_id.clone();
+        }
+
+        let run_start = std::time::Instant::now();
+        let result = handle.block_on(crate::run_in_runtime(
+            &mut isolate.runtime,
+            job.context,
+            &job.contract_path,
+            &job.contract_source,
+            job.request,
+            job.io,
+        ));
+        isolate.requests += 1;
+        eprintln!(
+            "  [TIMING] ═══ TOTAL V8 TIME (warm, worker={}): {}ms ═══",
+            worker_id,
+            run_start.elapsed().as_millis()
+        );
+
+        let failed = result.is_err();
+        let _ = job.reply.send(result);
+
+        // Reset after replying so cleanup never adds to request latency
+        let modules = module_count(&isolate.runtime);
+        let reusable = !failed
+            && isolate.requests < max_requests
+            && modules < max_modules
+            && match reset_isolate(&mut isolate.runtime) {
+                Ok(clean) => clean,
+                Err(e) => {
+                    eprintln!("[POOL] worker={} {}", worker_id, e);
+                    false
+                },
+            };
+
+        if !reusable {
+            eprintln!(
+                "[POOL] worker={} recycling isolate after {} request(s), {} module(s)",
+                worker_id,
+                isolate.requests,
+                modules
+            );
+            slot = None;
+        }
+    }
+}
+
+fn warm(worker_id: usize) -> Option<WarmIsolate> {
+    let warm_start = std::time::Instant::now();
+    match warm_isolate() {
+        Ok(isolate) => {
+            eprintln!("[POOL] worker={} isolate warmed in {}ms", worker_id, warm_start.elapsed().as_millis());
+            Some(isolate)
+        },
+        Err(e) => {
+            eprintln!("[POOL] worker={} failed to warm isolate: {}", worker_id, e);
+            None
+        },
+    }
+}
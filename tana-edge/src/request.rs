@@ -0,0 +1,274 @@
+// HTTP request context handed to contracts as a tana/net Request
+//
+// Built from the incoming axum request: the subpath after /:contract_id, the
+// parsed query string, an allowlisted subset of headers, the route params,
+// the client address and the method. Also builds the run's block context
+// (see context.rs) from TANA_BLOCK_CONTEXT and X-Tana-Block-* headers, with
+// the contract's own account from TANA_CONTRACT_ACCOUNTS.
+//
+// X-Tana-Block-Executor is the caller identity tana/tx authorizes against and
+// the other fields are what contracts see as the chain, so X-Tana-Block-*
+// headers are only accepted from a gateway sending the X-Tana-Gateway-Token
+// that matches TANA_GATEWAY_TOKEN. Without a token configured every block
+// header is rejected; TANA_ALLOW_UNVERIFIED_BLOCK_HEADERS=1 accepts them
+// anyway, for local development only.
+
+use std::collections::HashMap;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+
+use axum::http::{HeaderMap, Method, StatusCode};
+use serde::Serialize;
+
+use crate::context::{self, BlockContext};
+
+// Headers forwarded to contracts; everything else (cookies, credentials,
+// proxy and hop-by-hop headers) is dropped before the contract sees it
+const ALLOWED_HEADERS: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "accept-language",
+    "cache-control",
+    "content-length",
+    "content-type",
+    "if-match",
+    "if-modified-since",
+    "if-none-match",
+    "origin",
+    "referer",
+    "user-agent",
+    "x-request-id",
+];
+
+// Custom headers under this prefix are forwarded as well
+const ALLOWED_HEADER_PREFIX: &str = "x-tana-";
+
+// Block context fields by header: X-Tana-Block-Height, X-Tana-Block-Executor, ...
+const BLOCK_HEADER_PREFIX: &str = "x-tana-block-";
+
+// Proves X-Tana-Block-* headers come from the gateway; never forwarded to contracts
+const GATEWAY_TOKEN_HEADER: &str = "x-tana-gateway-token";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestContext {
+    pub path: String,
+    pub method: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub params: HashMap<String, String>,
+    pub ip: String,
+}
+
+impl RequestContext {
+    pub fn new(
+        method: &Method,
+        params: HashMap<String, String>,
+        query: HashMap<String, String>,
+        headers: &HeaderMap,
+        remote: SocketAddr,
+    ) -> RequestContext {
+        // Subpath below the contract: /:contract_id/*path
+        let path = format!("/{}", params.get("path").map(String::as_str).unwrap_or(""));
+
+        RequestContext {
+            path,
+            method: method.as_str().to_string(),
+            query,
+            headers: sanitize_headers(headers),
+            params,
+            ip: remote.ip().to_string(),
+        }
+    }
+}
+
+// Allowlisted headers with lowercase names; repeated headers are joined with ", "
+fn sanitize_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut sanitized: HashMap<String, String> = HashMap::new();
+
+    for (name, value) in headers {
+        let name = name.as_str();
+        if !ALLOWED_HEADERS.contains(&name) && !name.starts_with(ALLOWED_HEADER_PREFIX) {
+            continue;
+        }
+        if name == GATEWAY_TOKEN_HEADER {
+            continue;
+        }
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+
+        sanitized
+            .entry(name.to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    sanitized
+}
+
+// Block context every run starts from: the TANA_BLOCK_CONTEXT file, read once
+// per process; call at startup so a bad file fails fast. It applies to every
+// contract, so it can't name a contract account (see contract_accounts).
+pub fn base_block_context() -> &'static BlockContext {
+    static BASE: OnceLock<BlockContext> = OnceLock::new();
+    BASE.get_or_init(|| {
+        let base = match env::var("TANA_BLOCK_CONTEXT") {
+            Ok(path) => BlockContext::from_file(&path).expect("Failed to load block context"),
+            Err(_) => BlockContext::default(),
+        };
+        if base.contract_account.is_some() {
+            panic!("TANA_BLOCK_CONTEXT can't set contractAccount; map contracts to accounts in TANA_CONTRACT_ACCOUNTS");
+        }
+        base
+    })
+}
+
+// Each contract's own ledger account (block.contractAccount), by contract id:
+// the TANA_CONTRACT_ACCOUNTS file (.toml or .json), read once per process;
+// call at startup so a bad file fails fast
+//
+//   counter = "6f1c..."
+//   treasury = "a93b..."
+pub fn contract_accounts() -> &'static HashMap<String, String> {
+    static ACCOUNTS: OnceLock<HashMap<String, String>> = OnceLock::new();
+    ACCOUNTS.get_or_init(|| match env::var("TANA_CONTRACT_ACCOUNTS") {
+        Ok(path) => load_contract_accounts(&path).expect("Failed to load contract accounts"),
+        Err(_) => HashMap::new(),
+    })
+}
+
+fn load_contract_accounts(path: &str) -> Result<HashMap<String, String>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read contract accounts {}: {}", path, e))?;
+    if path.ends_with(".json") {
+        serde_json::from_str(&text).map_err(|e| format!("Invalid contract accounts {}: {}", path, e))
+    } else {
+        toml::from_str(&text).map_err(|e| format!("Invalid contract accounts {}: {}", path, e))
+    }
+}
+
+// Whether X-Tana-Block-* headers are honored (TANA_BLOCK_CONTEXT_HEADERS=0 turns
+// them off when clients reach the edge directly instead of through the gateway)
+fn block_headers_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        env::var("TANA_BLOCK_CONTEXT_HEADERS")
+            .map(|v| v != "0" && v != "false")
+            .unwrap_or(true)
+    })
+}
+
+// TANA_GATEWAY_TOKEN, read once per process (None when unset or empty)
+pub fn gateway_token() -> Option<&'static str> {
+    static TOKEN: OnceLock<Option<String>> = OnceLock::new();
+    TOKEN
+        .get_or_init(|| env::var("TANA_GATEWAY_TOKEN").ok().filter(|t| !t.is_empty()))
+        .as_deref()
+}
+
+// Whether X-Tana-Block-* headers are accepted without a gateway token
+// (TANA_ALLOW_UNVERIFIED_BLOCK_HEADERS=1): lets any client act as any user
+pub fn unverified_block_headers_allowed() -> bool {
+    static ALLOWED: OnceLock<bool> = OnceLock::new();
+    *ALLOWED.get_or_init(|| {
+        env::var("TANA_ALLOW_UNVERIFIED_BLOCK_HEADERS")
+            .map(|v| v == "1" || v == "true")
+            .unwrap_or(false)
+    })
+}
+
+// Compares every byte, so the time taken doesn't reveal how much of the token matched
+fn tokens_match(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len() && given.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// Whether the request carries the gateway token (never true without TANA_GATEWAY_TOKEN)
+pub fn from_gateway(headers: &HeaderMap) -> bool {
+    let given = headers.get(GATEWAY_TOKEN_HEADER).map(|v| v.as_bytes()).unwrap_or_default();
+    gateway_token().is_some_and(|token| tokens_match(given, token.as_bytes()))
+}
+
+// Block context of a request: the base context with X-Tana-Block-* headers on
+// top. The contract id always comes from the route (it scopes tana/data) and
+// the contract account from the contract id, and a header can lower the gas
+// limit but not raise it. Fails with 400 for malformed headers and 401 for
+// headers without a matching gateway token (or with no token configured).
+pub fn block_context(contract_id: &str, headers: &HeaderMap) -> Result<BlockContext, (StatusCode, String)> {
+    let base = base_block_context();
+    let mut block = base.clone();
+
+    let has_block_headers = headers.keys().any(|name| name.as_str().starts_with(BLOCK_HEADER_PREFIX));
+    if block_headers_enabled() && has_block_headers {
+        match gateway_token() {
+            Some(_) if !from_gateway(headers) => {
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    "X-Tana-Block-* headers require a valid X-Tana-Gateway-Token".to_string(),
+                ));
+            },
+            None if !unverified_block_headers_allowed() => {
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    "X-Tana-Block-* headers require TANA_GATEWAY_TOKEN and a matching X-Tana-Gateway-Token".to_string(),
+                ));
+            },
+            _ => {},
+        }
+
+        let invalid = |e: String| (StatusCode::BAD_REQUEST, e);
+        for field in context::FIELDS.iter().filter(|field| **field != "contract-id") {
+            let name = format!("{}{}", BLOCK_HEADER_PREFIX, field);
+            let Some(value) = headers.get(name.as_str()) else {
+                continue;
+            };
+            if *field == "contract-account" {
+                return Err(invalid(format!("{} can't be set by header (see TANA_CONTRACT_ACCOUNTS)", name)));
+            }
+            let value = value.to_str().map_err(|_| invalid(format!("Invalid {} header", name)))?;
+            block.set(field, value).map_err(|e| invalid(format!("Invalid {} header: {}", name, e)))?;
+        }
+        block.gas_limit = block.gas_limit.min(base.gas_limit);
+    }
+
+    block.contract_id = contract_id.to_string();
+    block.contract_account = contract_accounts().get(contract_id).cloned();
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gateway_requires_configured_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert(GATEWAY_TOKEN_HEADER, "".parse().unwrap());
+        assert!(!from_gateway(&headers));
+        assert!(!from_gateway(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn tokens_compare_whole() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secreT", b"secret"));
+        assert!(!tokens_match(b"secret", b"secret2"));
+        assert!(!tokens_match(b"", b"secret"));
+    }
+
+    // Runs without TANA_GATEWAY_TOKEN or TANA_ALLOW_UNVERIFIED_BLOCK_HEADERS set
+    #[test]
+    fn block_headers_need_gateway_token() {
+        let context = block_context("counter", &HeaderMap::new()).unwrap();
+        assert_eq!(context.contract_id, "counter");
+
+        for name in ["x-tana-block-height", "x-tana-block-timestamp", "x-tana-block-executor"] {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, "1".parse().unwrap());
+            let (status, _) = block_context("counter", &headers).unwrap_err();
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{}", name);
+        }
+    }
+}
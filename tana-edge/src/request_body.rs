@@ -0,0 +1,280 @@
+// Raw request body of the current run (Request.text/json/formData/arrayBuffer)
+//
+// The handler accepts any body up to TANA_MAX_BODY_SIZE bytes (default 1 MB,
+// larger bodies get a 413) and the contract decides how to read it.
+// formData() understands application/x-www-form-urlencoded and multipart/form-data.
+//
+// Compiled into build.rs along with ops.rs, so this file must only depend on
+// crates listed in both [dependencies] and [build-dependencies].
+
+use std::env;
+use std::sync::OnceLock;
+
+use deno_core::ToJsBuffer;
+use serde::Serialize;
+
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// Max request body size in bytes, read once per process
+pub fn max_body_size() -> usize {
+    static MAX_BODY_SIZE: OnceLock<usize> = OnceLock::new();
+    *MAX_BODY_SIZE.get_or_init(|| {
+        env::var("TANA_MAX_BODY_SIZE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|size| *size > 0)
+            .unwrap_or(DEFAULT_MAX_BODY_SIZE)
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RequestBody {
+    pub bytes: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+// One formData() value: a plain field, or an uploaded file (multipart only)
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum FormValue {
+    Field(String),
+    File {
+        filename: String,
+        #[serde(rename = "type")]
+        content_type: String,
+        data: ToJsBuffer,
+    },
+}
+
+impl RequestBody {
+    pub fn new(bytes: Vec<u8>, content_type: Option<String>) -> RequestBody {
+        RequestBody { bytes, content_type }
+    }
+
+    // UTF-8 text (invalid sequences become U+FFFD)
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes).into_owned()
+    }
+
+    // Second argument to handlers: the body parsed as JSON, {} when empty,
+    // null when it isn't JSON (use req.text() / req.formData() instead)
+    pub fn handler_arg(&self) -> serde_json::Value {
+        if self.bytes.iter().all(u8::is_ascii_whitespace) {
+            return serde_json::json!({});
+        }
+        serde_json::from_slice(&self.bytes).unwrap_or(serde_json::Value::Null)
+    }
+
+    // Form fields in body order (repeated names appear more than once)
+    pub fn form(&self) -> Result<Vec<(String, FormValue)>, String> {
+        let content_type = self.content_type.as_deref().unwrap_or("");
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+
+        match mime.as_str() {
+            "application/x-www-form-urlencoded" => Ok(form_urlencoded::parse(&self.bytes)
+                .map(|(name, value)| (name.into_owned(), FormValue::Field(value.into_owned())))
+                .collect()),
+            "multipart/form-data" => {
+                let boundary = header_param(content_type, "boundary")
+                    .ok_or_else(|| "multipart/form-data body without a boundary".to_string())?;
+                parse_multipart(&self.bytes, &boundary)
+            },
+            _ => Err(format!(
+                "formData() needs an application/x-www-form-urlencoded or multipart/form-data body, got '{}'",
+                content_type
+            )),
+        }
+    }
+}
+
+fn parse_multipart(bytes: &[u8], boundary: &str) -> Result<Vec<(String, FormValue)>, String> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let next_delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+    let mut pos = find(bytes, &delimiter, 0).ok_or("multipart body is missing its boundary")? + delimiter.len();
+    let mut fields = Vec::new();
+
+    // Each delimiter is followed by CRLF and a part, or by "--" after the last part
+    while !bytes[pos..].starts_with(b"--") {
+        let start = pos + skip_crlf(&bytes[pos..]);
+        let end = find(bytes, &next_delimiter, start).ok_or("multipart part is missing its closing boundary")?;
+        if let Some(field) = parse_part(&bytes[start..end])? {
+            fields.push(field);
+        }
+        pos = end + next_delimiter.len();
+    }
+
+    Ok(fields)
+}
+
+// Headers, blank line, content; parts without a name are skipped
+fn parse_part(part: &[u8]) -> Result<Option<(String, FormValue)>, String> {
+    let split = find(part, b"\r\n\r\n", 0).ok_or("multipart part is missing its headers")?;
+    let headers = String::from_utf8_lossy(&part[..split]);
+    let content = &part[split + 4..];
+
+    let mut disposition = None;
+    let mut content_type = None;
+    for line in headers.split("\r\n") {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-disposition" => disposition = Some(value.trim().to_string()),
+            "content-type" => content_type = Some(value.trim().to_string()),
+            _ => {},
+        }
+    }
+
+    let Some(disposition) = disposition else {
+        return Ok(None);
+    };
+    let Some(name) = header_param(&disposition, "name") else {
+        return Ok(None);
+    };
+
+    let value = match header_param(&disposition, "filename") {
+        Some(filename) => FormValue::File {
+            filename,
+            content_type: content_type.unwrap_or_else(|| "application/octet-stream".to_string()),
+            data: ToJsBuffer::from(content.to_vec()),
+        },
+        None => FormValue::Field(String::from_utf8_lossy(content).into_owned()),
+    };
+
+    Ok(Some((name, value)))
+}
+
+// `name=value` parameter of a header like Content-Type or Content-Disposition
+fn header_param(header: &str, name: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        Some(value.to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| i + from)
+}
+
+fn skip_crlf(bytes: &[u8]) -> usize {
+    if bytes.starts_with(b"\r\n") {
+        2
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(body: &str, content_type: &str) -> Result<Vec<(String, FormValue)>, String> {
+        RequestBody::new(body.as_bytes().to_vec(), Some(content_type.to_string())).form()
+    }
+
+    fn field(value: &FormValue) -> &str {
+        match value {
+            FormValue::Field(value) => value,
+            FormValue::File { .. } => panic!("expected a field"),
+        }
+    }
+
+    #[test]
+    fn urlencoded() {
+        let fields = form("a=1&b=hello+world&a=2", "application/x-www-form-urlencoded").unwrap();
+        let fields: Vec<(&str, &str)> = fields.iter().map(|(name, value)| (name.as_str(), field(value))).collect();
+        assert_eq!(fields, [("a", "1"), ("b", "hello world"), ("a", "2")]);
+    }
+
+    #[test]
+    fn multipart_fields_and_files() {
+        let body = "preamble\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            Hello\r\nworld\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            file body\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"raw\"; filename=\"b.bin\"\r\n\
+            \r\n\
+            \r\n\
+            --XyZ--\r\n";
+        let fields = form(body, "multipart/form-data; boundary=XyZ").unwrap();
+
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[0].0, "title");
+        assert_eq!(field(&fields[0].1), "Hello\r\nworld");
+        match &fields[1] {
+            (name, FormValue::File { filename, content_type, .. }) => {
+                assert_eq!(name, "upload");
+                assert_eq!(filename, "a.txt");
+                assert_eq!(content_type, "text/plain");
+            },
+            _ => panic!("expected a file"),
+        }
+        match &fields[2].1 {
+            FormValue::File { content_type, .. } => assert_eq!(content_type, "application/octet-stream"),
+            _ => panic!("expected a file"),
+        }
+    }
+
+    #[test]
+    fn multipart_quoted_boundary_and_unnamed_parts() {
+        let body = "--a b\r\n\
+            Content-Disposition: form-data\r\n\
+            \r\n\
+            skipped\r\n\
+            --a b\r\n\
+            content-disposition: form-data; NAME=kept\r\n\
+            \r\n\
+            1\r\n\
+            --a b--";
+        let fields = form(body, "Multipart/Form-Data; charset=utf-8; boundary=\"a b\"").unwrap();
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].0, "kept");
+        assert_eq!(field(&fields[0].1), "1");
+    }
+
+    #[test]
+    fn multipart_errors() {
+        assert!(form("--x--", "multipart/form-data").is_err());
+        assert!(form("no delimiter here", "multipart/form-data; boundary=x").is_err());
+        assert!(form("--x\r\nContent-Disposition: form-data; name=a\r\n\r\nunterminated", "multipart/form-data; boundary=x").is_err());
+        assert!(form("--x\r\nno headers\r\n--x--", "multipart/form-data; boundary=x").is_err());
+    }
+
+    #[test]
+    fn other_types_are_refused() {
+        assert!(form("{}", "application/json").is_err());
+        assert!(RequestBody::new(b"a=1".to_vec(), None).form().is_err());
+    }
+
+    #[test]
+    fn handler_arg() {
+        assert_eq!(RequestBody::new(b" \n".to_vec(), None).handler_arg(), serde_json::json!({}));
+        assert_eq!(RequestBody::new(b"{\"a\":1}".to_vec(), None).handler_arg(), serde_json::json!({ "a": 1 }));
+        assert_eq!(RequestBody::new(b"a=1".to_vec(), None).handler_arg(), serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,299 @@
+// Contract Response -> HTTP response
+//
+// Contracts return a tana/net Response ({status, headers, body}). Its status and
+// headers become the HTTP status and headers (minus hop-by-hop headers and the
+// ones the edge itself controls), and the body is serialized by Content-Type:
+// JSON for application/json and +json types, as-is for text types (text/*,
+// HTML, XML, JavaScript, forms), and base64-decoded bytes for anything else.
+//
+// A result that isn't a Response is sent as JSON, with status 200. Plain objects
+// count as one only when `status` is a number and they have no keys besides
+// status, headers and body, so data that happens to have a `status` or `body`
+// field isn't mistaken for a Response.
+//
+// ReadableStream / async iterator bodies are streamed instead (see stream.rs):
+// STREAM_SCRIPT sends the status and headers, then writes each chunk.
+
+use std::io;
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::Response;
+use base64::Engine;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+
+// Headers contracts can't set
+const BLOCKED_HEADERS: &[&str] = &[
+    // Hop-by-hop
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    // Set by the server from the body
+    "content-length",
+    // Shared by every contract on this origin, so owned by the edge
+    "set-cookie",
+    "strict-transport-security",
+    "access-control-allow-origin",
+    "access-control-allow-credentials",
+    "access-control-allow-headers",
+    "access-control-allow-methods",
+    "access-control-expose-headers",
+    "access-control-max-age",
+    "alt-svc",
+    "server",
+    "date",
+];
+
+// Read the run's result as JSON; a tana/net Response becomes exactly its
+// status, headers and body (see is_response), and ArrayBuffer / typed array
+// bodies are sent over as base64 (application/octet-stream unless the contract
+// set a type)
+pub const RESULT_SCRIPT: &str = r#"
+    (function() {
+      let result = globalThis.__contractResult || { status: 500, body: { error: "No result returned" } };
+      if (result instanceof __tanaImport('tana/net').Response) {
+        result = { status: Number(result.status) || 200, headers: result.headers || {}, body: result.body };
+      }
+      const isResponse = result !== null && typeof result === 'object' && typeof result.status === 'number' &&
+        Object.keys(result).every((key) => key === 'status' || key === 'headers' || key === 'body');
+      const body = isResponse ? result.body : undefined;
+
+      if (body instanceof ArrayBuffer || ArrayBuffer.isView(body)) {
+        const bytes = body instanceof ArrayBuffer
+          ? new Uint8Array(body)
+          : new Uint8Array(body.buffer, body.byteOffset, body.byteLength);
+        const chars = 'ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/';
+        let encoded = '';
+        for (let i = 0; i < bytes.length; i += 3) {
+          const n = (bytes[i] << 16) | ((bytes[i + 1] ?? 0) << 8) | (bytes[i + 2] ?? 0);
+          encoded += chars[(n >> 18) & 63] + chars[(n >> 12) & 63];
+          encoded += i + 1 < bytes.length ? chars[(n >> 6) & 63] : '=';
+          encoded += i + 2 < bytes.length ? chars[n & 63] : '=';
+        }
+
+        const headers = { ...(result.headers || {}) };
+        if (!Object.keys(headers).some((name) => name.toLowerCase() === 'content-type')) {
+          headers['Content-Type'] = 'application/octet-stream';
+        }
+        return JSON.stringify({ status: result.status, headers, body: encoded });
+      }
+
+      return JSON.stringify(result);
+    })()
+"#;
+
+// Runner step after the handler returned: streams a ReadableStream / async
+// iterator body chunk by chunk (strings as UTF-8, typed arrays as bytes, anything
+// else as JSON) and stops early when the client disconnects
+pub const STREAM_SCRIPT: &str = r#"
+          const streamed = globalThis.__contractResult;
+          const source = streamed?.body;
+          if (source && typeof source === 'object' &&
+              (typeof source.getReader === 'function' || typeof source[Symbol.asyncIterator] === 'function')) {
+            const ops = globalThis.__tanaCore.ops;
+            const chunks = async function* () {
+              if (typeof source[Symbol.asyncIterator] === 'function') {
+                yield* source;
+                return;
+              }
+              const reader = source.getReader();
+              try {
+                for (;;) {
+                  const { done, value } = await reader.read();
+                  if (done) return;
+                  yield value;
+                }
+              } finally {
+                await reader.cancel?.();
+              }
+            };
+
+            const head = { status: streamed.status || 200, headers: streamed.headers || {} };
+            if (ops.op_stream_start(head)) {
+              for await (const chunk of chunks()) {
+                const connected = chunk instanceof ArrayBuffer || ArrayBuffer.isView(chunk)
+                  ? await ops.op_stream_write(chunk instanceof ArrayBuffer ? new Uint8Array(chunk) : chunk)
+                  : await ops.op_stream_write_text(typeof chunk === 'string' ? chunk : JSON.stringify(chunk));
+                if (!connected) break;
+              }
+            }
+            globalThis.__contractResult = { ...head, body: null };
+          }
+"#;
+
+// Build the HTTP response for a contract result
+pub fn into_http(result: &serde_json::Value) -> Response {
+    let (status, headers, body) = if is_response(result) {
+        (status(result), contract_headers(&result["headers"]), &result["body"])
+    } else {
+        (StatusCode::OK, HeaderMap::new(), result)
+    };
+
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    match serialize_body(body, content_type.as_deref()) {
+        Ok((bytes, default_type)) => {
+            let mut response = Response::new(Body::from(bytes));
+            *response.status_mut() = status;
+            *response.headers_mut() = headers;
+            if let (None, Some(default_type)) = (content_type, default_type) {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_TYPE, HeaderValue::from_static(default_type));
+            }
+            response
+        },
+        Err(e) => {
+            eprintln!("[EXEC] Invalid contract response: {}", e);
+            let body = serde_json::json!({ "error": e }).to_string();
+            let mut response = Response::new(Body::from(body));
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        },
+    }
+}
+
+// Streamed response: status and headers now, the body as the contract writes it
+// A run that fails mid-stream ends the body with an error, so the connection is
+// aborted instead of the client taking a truncated body as complete
+pub fn into_streaming_http(
+    head: &serde_json::Value,
+    chunks: mpsc::Receiver<Vec<u8>>,
+    failed: mpsc::Receiver<String>,
+) -> Response {
+    let mut headers = contract_headers(&head["headers"]);
+    if !headers.contains_key(header::CONTENT_TYPE) {
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    }
+
+    let failed = ReceiverStream::new(failed).map(|error| Err(io::Error::other(error)));
+    let body = Body::from_stream(ReceiverStream::new(chunks).map(Ok).chain(failed));
+    let mut response = Response::new(body);
+    *response.status_mut() = status(head);
+    *response.headers_mut() = headers;
+    response
+}
+
+// {status: <number>, headers?, body?} and nothing else (RESULT_SCRIPT turns
+// tana/net Responses into exactly that)
+fn is_response(result: &serde_json::Value) -> bool {
+    match result.as_object() {
+        Some(fields) => {
+            fields.get("status").is_some_and(serde_json::Value::is_number)
+                && fields.keys().all(|key| matches!(key.as_str(), "status" | "headers" | "body"))
+        },
+        None => false,
+    }
+}
+
+fn status(result: &serde_json::Value) -> StatusCode {
+    result["status"]
+        .as_u64()
+        .and_then(|s| StatusCode::from_u16(s as u16).ok())
+        .unwrap_or(StatusCode::OK)
+}
+
+// Response headers from the contract, minus blocked and invalid ones
+// Array values become repeated headers
+fn contract_headers(headers: &serde_json::Value) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    let Some(headers) = headers.as_object() else {
+        return map;
+    };
+
+    for (name, value) in headers {
+        let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+            continue;
+        };
+        if BLOCKED_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+
+        let values = match value {
+            serde_json::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Null => continue,
+                other => other.to_string(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                map.append(name.clone(), value);
+            }
+        }
+    }
+
+    map
+}
+
+// Body bytes for the Content-Type, plus the type to send when the contract set none
+fn serialize_body(
+    body: &serde_json::Value,
+    content_type: Option<&str>,
+) -> Result<(Vec<u8>, Option<&'static str>), String> {
+    if body.is_null() {
+        return Ok((Vec::new(), None));
+    }
+
+    let Some(content_type) = content_type else {
+        return Ok(match body {
+            serde_json::Value::String(text) => (text.clone().into_bytes(), Some("text/plain; charset=utf-8")),
+            other => (other.to_string().into_bytes(), Some("application/json")),
+        });
+    };
+
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    if is_json(&mime) {
+        return Ok((body.to_string().into_bytes(), None));
+    }
+    if is_text(&mime) {
+        return Ok(match body {
+            serde_json::Value::String(text) => (text.clone().into_bytes(), None),
+            other => (other.to_string().into_bytes(), None),
+        });
+    }
+
+    // Binary: the body is base64 (typed arrays are encoded by RESULT_SCRIPT)
+    match body {
+        serde_json::Value::String(encoded) => base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map(|bytes| (bytes, None))
+            .map_err(|e| format!("{} body must be base64: {}", mime, e)),
+        other => Ok((other.to_string().into_bytes(), None)),
+    }
+}
+
+fn is_json(mime: &str) -> bool {
+    mime == "application/json" || mime.ends_with("+json")
+}
+
+fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime,
+            "application/xml" | "application/javascript" | "application/x-www-form-urlencoded"
+        )
+}
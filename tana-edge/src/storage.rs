@@ -0,0 +1,366 @@
+// Storage backends for the tana/data ops
+//
+// The ops in main.rs keep their own staging buffer and only talk to a backend
+// for reads and for the final commit. A backend must apply a commit atomically:
+// either every staged change lands or none of them do, and the quota check sees
+// the same entries the commit writes over (no other commit in between).
+//
+// Every call takes a namespace (the contract id) so contracts never see or
+// overwrite each other's keys.
+
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use redis::AsyncCommands;
+
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+// Staged changes: Some(value) = set, None = delete
+pub type Changes = HashMap<String, Option<String>>;
+
+// Per-namespace limits a commit must keep the namespace within
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub max_bytes: usize,
+    pub max_keys: usize,
+}
+
+impl Quota {
+    // Check the namespace after `changes` are applied over `entries`
+    pub fn check(&self, entries: &HashMap<String, String>, changes: &Changes) -> Result<(), String> {
+        let mut total_size = 0;
+        let mut total_keys = 0;
+
+        // Existing keys, unless overwritten or deleted by the commit
+        for (key, value) in entries.iter() {
+            if changes.contains_key(key) {
+                continue;
+            }
+            total_size += key.len() + value.len();
+            total_keys += 1;
+        }
+
+        // Staged values
+        for (key, value) in changes.iter() {
+            if let Some(ref val) = value {
+                total_size += key.len() + val.len();
+                total_keys += 1;
+            }
+        }
+
+        self.check_totals(total_size, total_keys)
+    }
+
+    fn check_totals(&self, total_size: usize, total_keys: usize) -> Result<(), String> {
+        if total_size > self.max_bytes {
+            return Err(format!("Storage limit exceeded: {} bytes (max {})", total_size, self.max_bytes));
+        }
+        if total_keys > self.max_keys {
+            return Err(format!("Too many keys: {} (max {})", total_keys, self.max_keys));
+        }
+        Ok(())
+    }
+}
+
+pub trait StorageBackend: Send + Sync {
+    // Short name used in startup logs
+    fn name(&self) -> &'static str;
+
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a str) -> StorageFuture<'a, Option<String>>;
+
+    fn keys<'a>(&'a self, namespace: &'a str) -> StorageFuture<'a, Vec<String>>;
+
+    // Apply all staged changes atomically if the namespace stays within `quota`
+    // The inner Err is the quota error; nothing is written then
+    fn commit<'a>(
+        &'a self,
+        namespace: &'a str,
+        changes: &'a Changes,
+        quota: Quota,
+    ) -> StorageFuture<'a, Result<(), String>>;
+
+    fn clear<'a>(&'a self, namespace: &'a str) -> StorageFuture<'a, ()>;
+}
+
+// ========== In-memory backend ==========
+
+// Process-local HashMap per namespace (matches playground localStorage, lost on restart)
+#[derive(Default)]
+pub struct MemoryStorage {
+    store: Mutex<HashMap<String, HashMap<String, String>>>,
+}
+
+impl StorageBackend for MemoryStorage {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a str) -> StorageFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let store = self.store.lock().unwrap();
+            Ok(store.get(namespace).and_then(|ns| ns.get(key)).cloned())
+        })
+    }
+
+    fn keys<'a>(&'a self, namespace: &'a str) -> StorageFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let store = self.store.lock().unwrap();
+            Ok(store.get(namespace).map(|ns| ns.keys().cloned().collect()).unwrap_or_default())
+        })
+    }
+
+    fn commit<'a>(
+        &'a self,
+        namespace: &'a str,
+        changes: &'a Changes,
+        quota: Quota,
+    ) -> StorageFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            // Single lock for the check and the whole batch keeps the commit atomic
+            let mut all = self.store.lock().unwrap();
+            let store = all.entry(namespace.to_string()).or_default();
+            if let Err(e) = quota.check(store, changes) {
+                return Ok(Err(e));
+            }
+            for (key, value) in changes.iter() {
+                if let Some(ref val) = value {
+                    store.insert(key.clone(), val.clone());
+                } else {
+                    store.remove(key);
+                }
+            }
+            Ok(Ok(()))
+        })
+    }
+
+    fn clear<'a>(&'a self, namespace: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            self.store.lock().unwrap().remove(namespace);
+            Ok(())
+        })
+    }
+}
+
+// ========== Redis backend ==========
+
+// Prefix for the per-contract hashes holding tana/data entries
+const REDIS_DATA_PREFIX: &str = "tana:data";
+
+// Quota check and commit in one script, so no other commit lands in between
+// (WATCH doesn't work on the shared multiplexed connection)
+// KEYS[1] = hash, ARGV = max bytes, max keys, then key, "set" | "del", value per change
+// Returns {"ok", 0}, or {"bytes" | "keys", total} over the quota with nothing written
+const REDIS_COMMIT_SCRIPT: &str = r#"
+local max_bytes, max_keys = tonumber(ARGV[1]), tonumber(ARGV[2])
+local staged = {}
+for i = 3, #ARGV, 3 do staged[ARGV[i]] = true end
+
+local bytes, keys = 0, 0
+local entries = redis.call('HGETALL', KEYS[1])
+for i = 1, #entries, 2 do
+  if not staged[entries[i]] then
+    bytes = bytes + #entries[i] + #entries[i + 1]
+    keys = keys + 1
+  end
+end
+for i = 3, #ARGV, 3 do
+  if ARGV[i + 1] == 'set' then
+    bytes = bytes + #ARGV[i] + #ARGV[i + 2]
+    keys = keys + 1
+  end
+end
+if bytes > max_bytes then return {'bytes', bytes} end
+if keys > max_keys then return {'keys', keys} end
+
+for i = 3, #ARGV, 3 do
+  if ARGV[i + 1] == 'set' then
+    redis.call('HSET', KEYS[1], ARGV[i], ARGV[i + 2])
+  else
+    redis.call('HDEL', KEYS[1], ARGV[i])
+  end
+end
+return {'ok', 0}
+"#;
+
+// Each namespace is one Redis hash (tana:data:<contract_id>) so state survives
+// restarts and is shared by every edge instance pointed at the same server
+pub struct RedisStorage {
+    conn: redis::aio::ConnectionManager,
+}
+
+impl RedisStorage {
+    pub async fn connect(url: &str) -> Result<Self, String> {
+        let client = redis::Client::open(url)
+            .map_err(|e| format!("Invalid Redis URL {}: {}", url, e))?;
+        let conn = redis::aio::ConnectionManager::new(client)
+            .await
+            .map_err(|e| format!("Failed to connect to Redis at {}: {}", url, e))?;
+
+        Ok(RedisStorage { conn })
+    }
+
+    fn hash_key(namespace: &str) -> String {
+        format!("{}:{}", REDIS_DATA_PREFIX, namespace)
+    }
+}
+
+impl StorageBackend for RedisStorage {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn get<'a>(&'a self, namespace: &'a str, key: &'a str) -> StorageFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let mut conn = self.conn.clone();
+            conn.hget(Self::hash_key(namespace), key)
+                .await
+                .map_err(|e| format!("Redis HGET failed: {}", e))
+        })
+    }
+
+    fn keys<'a>(&'a self, namespace: &'a str) -> StorageFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut conn = self.conn.clone();
+            conn.hkeys(Self::hash_key(namespace))
+                .await
+                .map_err(|e| format!("Redis HKEYS failed: {}", e))
+        })
+    }
+
+    fn commit<'a>(
+        &'a self,
+        namespace: &'a str,
+        changes: &'a Changes,
+        quota: Quota,
+    ) -> StorageFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            if changes.is_empty() {
+                return Ok(Ok(()));
+            }
+
+            // Scripts run atomically, so readers never observe a half-applied commit
+            let script = redis::Script::new(REDIS_COMMIT_SCRIPT);
+            let mut invocation = script.key(Self::hash_key(namespace));
+            invocation.arg(quota.max_bytes).arg(quota.max_keys);
+            for (key, value) in changes.iter() {
+                match value {
+                    Some(val) => invocation.arg(key).arg("set").arg(val),
+                    None => invocation.arg(key).arg("del").arg(""),
+                };
+            }
+
+            let mut conn = self.conn.clone();
+            let (outcome, total): (String, usize) = invocation
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| format!("Redis commit script failed: {}", e))?;
+            Ok(match outcome.as_str() {
+                "bytes" => quota.check_totals(total, 0),
+                "keys" => quota.check_totals(0, total),
+                _ => Ok(()),
+            })
+        })
+    }
+
+    fn clear<'a>(&'a self, namespace: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let mut conn = self.conn.clone();
+            conn.del::<_, ()>(Self::hash_key(namespace))
+                .await
+                .map_err(|e| format!("Redis DEL failed: {}", e))
+        })
+    }
+}
+
+// ========== Backend selection ==========
+
+// TANA_STORAGE=memory (default) or redis; Redis uses REDIS_URL
+pub async fn from_env() -> Result<Arc<dyn StorageBackend>, String> {
+    let kind = env::var("TANA_STORAGE").unwrap_or_else(|_| "memory".to_string());
+
+    match kind.as_str() {
+        "memory" => Ok(Arc::new(MemoryStorage::default())),
+        "redis" => {
+            let url = env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string());
+            Ok(Arc::new(RedisStorage::connect(&url).await?))
+        },
+        other => Err(format!("Unknown TANA_STORAGE backend: {} (expected memory or redis)", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTA: Quota = Quota { max_bytes: 32, max_keys: 2 };
+
+    fn changes(items: &[(&str, Option<&str>)]) -> Changes {
+        items.iter().map(|(k, v)| (k.to_string(), v.map(str::to_string))).collect()
+    }
+
+    #[tokio::test]
+    async fn memory_commit_sets_and_deletes() {
+        let storage = MemoryStorage::default();
+        storage.commit("counter", &changes(&[("a", Some("1")), ("b", Some("2"))]), QUOTA).await.unwrap().unwrap();
+        storage.commit("counter", &changes(&[("a", None), ("b", Some("3"))]), QUOTA).await.unwrap().unwrap();
+
+        assert_eq!(storage.get("counter", "a").await.unwrap(), None);
+        assert_eq!(storage.get("counter", "b").await.unwrap().as_deref(), Some("3"));
+        assert_eq!(storage.keys("counter").await.unwrap(), vec!["b".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn memory_commit_over_quota_writes_nothing() {
+        let storage = MemoryStorage::default();
+        storage.commit("counter", &changes(&[("a", Some("1"))]), QUOTA).await.unwrap().unwrap();
+
+        let too_many = changes(&[("b", Some("2")), ("c", Some("3"))]);
+        let err = storage.commit("counter", &too_many, QUOTA).await.unwrap().unwrap_err();
+        assert!(err.starts_with("Too many keys: 3"), "{}", err);
+
+        let too_big = changes(&[("a", Some(&"x".repeat(40)))]);
+        let err = storage.commit("counter", &too_big, QUOTA).await.unwrap().unwrap_err();
+        assert!(err.starts_with("Storage limit exceeded: 41 bytes"), "{}", err);
+
+        assert_eq!(storage.keys("counter").await.unwrap(), vec!["a".to_string()]);
+        assert_eq!(storage.get("counter", "a").await.unwrap().as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn memory_overwrites_and_deletes_count_against_quota_once() {
+        let storage = MemoryStorage::default();
+        storage.commit("counter", &changes(&[("a", Some("1")), ("b", Some("2"))]), QUOTA).await.unwrap().unwrap();
+
+        // Replacing b with c keeps the namespace at two keys
+        let swap = changes(&[("b", None), ("c", Some("3"))]);
+        storage.commit("counter", &swap, QUOTA).await.unwrap().unwrap();
+        let mut keys = storage.keys("counter").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, vec!["a".to_string(), "c".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn memory_namespaces_are_separate() {
+        let storage = MemoryStorage::default();
+        storage.commit("counter", &changes(&[("a", Some("1")), ("b", Some("2"))]), QUOTA).await.unwrap().unwrap();
+        // The other namespace has its own quota
+        storage.commit("voting", &changes(&[("a", Some("9")), ("c", Some("8"))]), QUOTA).await.unwrap().unwrap();
+
+        assert_eq!(storage.get("counter", "a").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(storage.get("voting", "a").await.unwrap().as_deref(), Some("9"));
+        assert_eq!(storage.get("voting", "b").await.unwrap(), None);
+
+        storage.clear("counter").await.unwrap();
+        assert!(storage.keys("counter").await.unwrap().is_empty());
+        assert_eq!(storage.keys("voting").await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn redis_hashes_per_namespace() {
+        assert_eq!(RedisStorage::hash_key("counter"), "tana:data:counter");
+        assert_ne!(RedisStorage::hash_key("counter"), RedisStorage::hash_key("voting"));
+    }
+}
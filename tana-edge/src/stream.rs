@@ -0,0 +1,195 @@
+// Streaming response bodies
+//
+// A contract whose Response body is a ReadableStream or an async iterator
+// (see Response.stream / Response.sse) gets its status and headers sent as soon
+// as the handler returns, then every chunk as it is produced. The HTTP handler
+// holds the receiving end; the run's OpState holds the sending end until the run
+// finishes (ops::end_run), which ends the HTTP body, or aborts it when the run
+// failed after the head was sent.
+//
+// Once streaming, a run is bounded by the stream limits in limits.rs (idle time
+// between chunks and total stream time) instead of the request's wall clock.
+// A streaming run keeps its pooled worker busy, so the pool caps how many
+// streams are open at once (TANA_POOL_MAX_STREAMS, see pool.rs).
+//
+// Compiled into build.rs along with ops.rs, so this file must only depend on
+// crates listed in both [dependencies] and [build-dependencies].
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+
+// Chunks buffered before op_stream_write waits for the client to catch up
+const STREAM_BUFFER: usize = 16;
+
+// Sending end, kept in the run's OpState
+pub struct ResponseStream {
+    head: Option<oneshot::Sender<serde_json::Value>>,
+    chunks: mpsc::Sender<Vec<u8>>,
+    failed: mpsc::Sender<String>,
+    progress: StreamProgress,
+    // Open-stream slots shared by the pool, and the one this stream holds
+    slots: Option<Arc<Semaphore>>,
+    slot: Option<OwnedSemaphorePermit>,
+}
+
+// Receiving end, kept by the HTTP handler
+pub struct StreamReceiver {
+    // {status, headers} of the streamed Response
+    pub head: oneshot::Receiver<serde_json::Value>,
+    pub chunks: mpsc::Receiver<Vec<u8>>,
+    // Why the run failed after the head was sent (at most one message, after the last chunk)
+    pub failed: mpsc::Receiver<String>,
+}
+
+// When the stream sent its head and its latest chunk (read by the run's watchdog)
+#[derive(Clone, Default)]
+pub struct StreamProgress(Arc<Mutex<Option<(Instant, Instant)>>>);
+
+impl StreamProgress {
+    // (started, last progress); None until the head is sent
+    pub fn times(&self) -> Option<(Instant, Instant)> {
+        *self.0.lock().unwrap()
+    }
+
+    fn touch(&self) {
+        let now = Instant::now();
+        let mut times = self.0.lock().unwrap();
+        *times = Some((times.map_or(now, |(started, _)| started), now));
+    }
+}
+
+// Handed to op_stream_write for each chunk
+#[derive(Clone)]
+pub struct ChunkSender {
+    chunks: mpsc::Sender<Vec<u8>>,
+    progress: StreamProgress,
+}
+
+impl ChunkSender {
+    // Waits while the client is behind; false once it has disconnected
+    pub async fn send(&self, chunk: Vec<u8>) -> bool {
+        let sent = self.chunks.send(chunk).await.is_ok();
+        if sent {
+            self.progress.touch();
+        }
+        sent
+    }
+}
+
+pub fn channel() -> (ResponseStream, StreamReceiver) {
+    let (head_tx, head_rx) = oneshot::channel();
+    let (chunks_tx, chunks_rx) = mpsc::channel(STREAM_BUFFER);
+    let (failed_tx, failed_rx) = mpsc::channel(1);
+
+    (
+        ResponseStream {
+            head: Some(head_tx),
+            chunks: chunks_tx,
+            failed: failed_tx,
+            progress: StreamProgress::default(),
+            slots: None,
+            slot: None,
+        },
+        StreamReceiver {
+            head: head_rx,
+            chunks: chunks_rx,
+            failed: failed_rx,
+        },
+    )
+}
+
+impl ResponseStream {
+    // Take one of `slots` while streaming (the pool's open-stream cap)
+    pub fn limit(&mut self, slots: Arc<Semaphore>) {
+        self.slots = Some(slots);
+    }
+
+    // Send the status and headers; false if already started or the client is gone
+    // Fails when every open-stream slot is taken
+    pub fn start(&mut self, head: serde_json::Value) -> Result<bool, String> {
+        let Some(head_tx) = self.head.take() else {
+            return Ok(false);
+        };
+        if let Some(slots) = &self.slots {
+            let slot = slots
+                .clone()
+                .try_acquire_owned()
+                .map_err(|_| "Too many open response streams (TANA_POOL_MAX_STREAMS)".to_string())?;
+            self.slot = Some(slot);
+        }
+
+        let started = head_tx.send(head).is_ok();
+        if started {
+            self.progress.touch();
+        }
+        Ok(started)
+    }
+
+    pub fn sender(&self) -> ChunkSender {
+        ChunkSender {
+            chunks: self.chunks.clone(),
+            progress: self.progress.clone(),
+        }
+    }
+
+    pub fn progress(&self) -> StreamProgress {
+        self.progress.clone()
+    }
+
+    // Abort a started body with `error` instead of ending it cleanly; false if
+    // the head was never sent (the error goes out as a normal response then)
+    pub fn fail(&mut self, error: &str) -> bool {
+        if self.progress.times().is_none() {
+            return false;
+        }
+        self.failed.try_send(error.to_string()).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_share_the_pool_slots() {
+        let slots = Arc::new(Semaphore::new(1));
+        let (mut first, _first_rx) = channel();
+        let (mut second, _second_rx) = channel();
+        first.limit(slots.clone());
+        second.limit(slots.clone());
+
+        assert_eq!(first.start(serde_json::json!({})), Ok(true));
+        assert!(second.start(serde_json::json!({})).is_err());
+        drop(first);
+        assert_eq!(slots.available_permits(), 1);
+    }
+
+    #[test]
+    fn failures_abort_only_started_streams() {
+        let (mut unstarted, mut unstarted_rx) = channel();
+        assert!(!unstarted.fail("before head"));
+        drop(unstarted);
+        assert!(unstarted_rx.failed.try_recv().is_err());
+
+        let (mut stream, mut rx) = channel();
+        assert_eq!(stream.start(serde_json::json!({ "status": 200 })), Ok(true));
+        assert!(stream.fail("Stream timed out"));
+        assert_eq!(rx.failed.try_recv().as_deref(), Ok("Stream timed out"));
+    }
+
+    #[tokio::test]
+    async fn chunks_move_the_progress() {
+        let (mut stream, mut rx) = channel();
+        assert!(stream.progress().times().is_none());
+        assert_eq!(stream.start(serde_json::json!({})), Ok(true));
+        let (started, first) = stream.progress().times().unwrap();
+
+        assert!(stream.sender().send(b"data".to_vec()).await);
+        let (still_started, last) = stream.progress().times().unwrap();
+        assert_eq!(started, still_started);
+        assert!(last >= first);
+        assert_eq!(rx.chunks.recv().await.as_deref(), Some(&b"data"[..]));
+    }
+}
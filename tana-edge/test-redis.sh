@@ -0,0 +1,93 @@
+#!/usr/bin/env bash
+#
+# Redis storage backend check for tana-edge
+#
+# Starts a throwaway redis-server, runs tana-edge against it, and verifies
+# that tana/data state survives an edge restart.
+#
+# Usage:
+#   ./test-redis.sh              # Uses port 6390 for the stand-in server
+#   REDIS_PORT=7000 ./test-redis.sh
+#
+
+set -e
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+BLUE='\033[0;34m'
+NC='\033[0m' # No Color
+
+# Configuration
+REDIS_PORT=${REDIS_PORT:-6390}
+BASE_URL="http://127.0.0.1:8180"
+CONTRACT_ID="modules"
+
+REDIS_PID=""
+EDGE_PID=""
+
+cleanup() {
+    [ -n "$EDGE_PID" ] && kill "$EDGE_PID" 2>/dev/null || true
+    [ -n "$REDIS_PID" ] && kill "$REDIS_PID" 2>/dev/null || true
+}
+trap cleanup EXIT
+
+for tool in redis-server redis-cli curl; do
+    if ! command -v "$tool" &> /dev/null; then
+        echo -e "${RED}Error: $tool is required but not installed${NC}"
+        exit 1
+    fi
+done
+
+start_edge() {
+    TANA_STORAGE=redis REDIS_URL="redis://127.0.0.1:$REDIS_PORT" \
+        ./target/release/tana-edge > /dev/null 2>&1 &
+    EDGE_PID=$!
+
+    for _ in $(seq 1 50); do
+        if curl -s -o /dev/null "$BASE_URL/$CONTRACT_ID"; then
+            return
+        fi
+        sleep 0.1
+    done
+    echo -e "${RED}Error: tana-edge did not start${NC}"
+    exit 1
+}
+
+stop_edge() {
+    kill "$EDGE_PID"
+    wait "$EDGE_PID" 2>/dev/null || true
+    EDGE_PID=""
+}
+
+visit_count() {
+    curl -s "$BASE_URL/$CONTRACT_ID" | grep -o '"visitCount": *[0-9]*' | grep -o '[0-9]*$'
+}
+
+echo -e "${BLUE}Building tana-edge...${NC}"
+cargo build --release
+
+echo -e "${BLUE}Starting redis-server on port $REDIS_PORT (no persistence)...${NC}"
+redis-server --port "$REDIS_PORT" --save "" --appendonly no > /dev/null &
+REDIS_PID=$!
+sleep 0.5
+redis-cli -p "$REDIS_PORT" FLUSHALL > /dev/null
+
+# start_edge already issued one request while waiting for the server
+start_edge
+first=$(visit_count)
+stop_edge
+
+start_edge
+second=$(visit_count)
+stop_edge
+
+echo "visitCount before restart: $first"
+echo "visitCount after restart:  $second"
+
+if [ "$second" -gt "$first" ]; then
+    echo -e "${GREEN}✓ tana/data state survived an edge restart${NC}"
+else
+    echo -e "${RED}✗ tana/data state was lost across restart${NC}"
+    exit 1
+fi
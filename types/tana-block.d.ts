@@ -61,6 +61,8 @@ declare module "tana:block" {
     readonly previousHash: string | null;
     readonly executor: string;
     readonly contractId: string | null;
+    // Ledger account holding the contract's own funds (tx.transfer may debit it)
+    readonly contractAccount: string | null;
     readonly gasLimit: number;
     readonly gasUsed: number;
     readonly MAX_BATCH_QUERY: 10;
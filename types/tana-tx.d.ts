@@ -18,11 +18,16 @@ declare module "tana:tx" {
   export interface TransactionResult {
     success: boolean;
     changes: TransactionChange[];
+    // Ledger transaction ids, one per change (empty unless success)
+    transactionIds: string[];
     gasUsed: number;
     error?: string;
+    // Change the ledger refused, when it names one
+    failedIndex?: number | null;
   }
 
   interface TransactionModule {
+    // fromId must be block.executor or block.contractAccount
     transfer(
       fromId: string,
       toId: string,
@@ -30,6 +35,7 @@ declare module "tana:tx" {
       currencyCode: string
     ): void;
 
+    // System contracts only (TANA_SYSTEM_CONTRACTS)
     setBalance(
       ownerId: string,
       amount: number,
@@ -1,22 +1,22 @@
 declare module "tana:utils" {
   /**
-   * Whitelisted fetch API for Tana
+   * Egress-controlled fetch API for Tana
    *
-   * Follows the standard Fetch API spec, but only allows requests to:
-   * - pokeapi.co (testing)
-   * - *.tana.dev (Tana infrastructure)
-   * - localhost / 127.0.0.1 (local development)
+   * Follows the standard Fetch API spec, but only allows requests to hosts
+   * permitted by the runtime's egress policy. Without an operator policy only
+   * tana.dev and *.tana.dev (Tana infrastructure) are allowed; localhost and
+   * other private addresses are refused unless the operator opts in.
    *
    * @param url - The URL to fetch from
    * @param options - Optional fetch options (method, headers, body, etc.)
    * @returns Promise that resolves to a Response-like object
-   * @throws Error if domain is not whitelisted
+   * @throws Error if the egress policy blocks the URL
    *
    * @example
    * ```typescript
    * import { fetch } from 'tana/utils'
    *
-   * const response = await fetch('https://pokeapi.co/api/v2/pokemon/pikachu')
+   * const response = await fetch('https://api.tana.dev/status')
    * const data = await response.json()
    * console.log(data.name)
    * ```